@@ -0,0 +1,91 @@
+//! Compares the memory-mapped search engine against the streaming fallback on a large generated
+//! log file. Run with `cargo bench --bench mmap`; set `GREPRS_BENCH_MB` to change the size of the
+//! log (it defaults to 2 GiB, so make sure there's room in the temp directory).
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 3;
+
+fn main() {
+    let megabytes: u64 = std::env::var("GREPRS_BENCH_MB")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(2048);
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let log = dir.path().join("service.log");
+    println!("generating {megabytes} MiB log at {}", log.display());
+    write_log(&log, megabytes * 1024 * 1024);
+
+    let cases: [(&str, &[&str]); 3] = [
+        ("rare literal", &["ERROR"]),
+        ("ignore case literal", &["-i", "error"]),
+        ("regex", &["-r", r"status=5\d\d"]),
+    ];
+    for (name, needle) in cases {
+        let mapped = best_time(&log, needle, false);
+        let streamed = best_time(&log, needle, true);
+        println!(
+            "{name:>20}: mmap {:>8.2?} ({:>7.1} MiB/s)  stream {:>8.2?} ({:>7.1} MiB/s)  speedup {:.2}x",
+            mapped,
+            throughput(megabytes, mapped),
+            streamed,
+            throughput(megabytes, streamed),
+            streamed.as_secs_f64() / mapped.as_secs_f64(),
+        );
+    }
+}
+
+/// Writes roughly `size` bytes of access-log-like lines, with an error every 10,000 lines.
+fn write_log(path: &Path, size: u64) {
+    let mut writer = BufWriter::new(File::create(path).expect("failed to create log"));
+    let mut written = 0;
+    let mut number: u64 = 0;
+    while written < size {
+        let (level, status) = if number % 10_000 == 9_999 {
+            ("ERROR", 500 + number % 4)
+        } else {
+            ("INFO", 200 + number % 5)
+        };
+        let line = format!(
+            "2024-10-10T12:{:02}:{:02}Z {level} request id={number} path=/api/v1/items/{} \
+             status={status} latency_ms={}\n",
+            number / 60 % 60,
+            number % 60,
+            number % 997,
+            number % 250,
+        );
+        writer
+            .write_all(line.as_bytes())
+            .expect("failed to write log");
+        written += line.len() as u64;
+        number += 1;
+    }
+    writer.flush().expect("failed to write log");
+}
+
+/// Runs greprs over `log` a few times and returns the fastest wall-clock time.
+fn best_time(log: &Path, needle: &[&str], no_mmap: bool) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut command = Command::new(env!("CARGO_BIN_EXE_greprs"));
+            if no_mmap {
+                command.arg("--no-mmap");
+            }
+            command.args(needle).arg(log).stdout(Stdio::null());
+            let start = Instant::now();
+            let status = command.status().expect("failed to run greprs");
+            let elapsed = start.elapsed();
+            assert!(status.success(), "greprs failed: {status}");
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn throughput(megabytes: u64, elapsed: Duration) -> f64 {
+    megabytes as f64 / elapsed.as_secs_f64()
+}
@@ -0,0 +1,284 @@
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// The name of a project's config file, looked for in the current directory and its parents.
+pub const PROJECT_FILE: &str = ".greprs.toml";
+
+/// Options that take a list of `key=value` pairs separated by colons, like the theme `--colors`
+/// takes, which config files can also give as a table.
+const TABLE_OPTIONS: &[&str] = &["colors"];
+
+/// A config file entry, as the command line arguments it stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    /// The id of the option it sets.
+    id: String,
+    args: Vec<String>,
+}
+
+/// The config files to read, lowest priority first: the user's `~/.config/greprs/config.toml`
+/// (under `$XDG_CONFIG_HOME` instead, if that's set), then the project's, if there is one.
+pub fn find_files(dir: &Path) -> Vec<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    let user = config_home
+        .map(|home| home.join("greprs").join("config.toml"))
+        .filter(|path| path.is_file());
+    user.into_iter().chain(project_file(dir)).collect()
+}
+
+/// The closest `.greprs.toml` in `dir` or one of its parents.
+fn project_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Reads the settings from each of `paths`, with settings in later files replacing the same
+/// options' settings in earlier ones.
+pub fn load(command: &Command, paths: &[PathBuf]) -> Result<Vec<Setting>> {
+    let mut loaded: Vec<Setting> = Vec::new();
+    for path in paths {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let file = parse(command, &contents)
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+        layer(&mut loaded, file);
+    }
+    Ok(loaded)
+}
+
+/// Adds a higher priority file's settings to `settings`, replacing any for the same options.
+fn layer(settings: &mut Vec<Setting>, file: Vec<Setting>) {
+    settings.retain(|setting| !file.iter().any(|replaced| replaced.id == setting.id));
+    settings.extend(file);
+}
+
+/// Parses a config file. Each key is the long name of an option: `true` turns a flag on, a string
+/// or number is the option's value, and an array gives the option once for each element. `false`
+/// leaves a flag off, even if a lower priority file turned it on. Options in [`TABLE_OPTIONS`]
+/// can be given a table instead, like a `[colors]` section with `ms = "01;32"` in it.
+pub fn parse(command: &Command, contents: &str) -> Result<Vec<Setting>> {
+    let table: Table = contents.parse()?;
+    table
+        .iter()
+        .map(|(key, value)| {
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()))
+                .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+                .with_context(|| format!("unknown option {key:?}"))?;
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            let mut args = Vec::with_capacity(values.len());
+            for value in values {
+                match value {
+                    Value::Boolean(true) => args.push(format!("--{key}")),
+                    Value::Boolean(false) => {}
+                    Value::String(string) => args.push(format!("--{key}={string}")),
+                    Value::Integer(number) => args.push(format!("--{key}={number}")),
+                    Value::Float(number) => args.push(format!("--{key}={number}")),
+                    Value::Table(table) if TABLE_OPTIONS.contains(&key.as_str()) => {
+                        args.push(format!("--{key}={}", join_table(key, table)?));
+                    }
+                    _ => bail!("{key} should be a boolean, string or number, or an array of them"),
+                }
+            }
+            Ok(Setting {
+                id: arg.get_id().to_string(),
+                args,
+            })
+        })
+        .collect()
+}
+
+/// Joins a table's entries into `key=value` pairs separated by colons.
+fn join_table(option: &str, table: &Table) -> Result<String> {
+    let pairs = table
+        .iter()
+        .map(|(key, value)| match value {
+            Value::String(value) => Ok(format!("{key}={value}")),
+            Value::Integer(value) => Ok(format!("{key}={value}")),
+            _ => bail!("{option}.{key} should be a string or number"),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(pairs.join(":"))
+}
+
+/// Puts the settings' arguments in front of the command line's, so the command line wins.
+///
+/// Settings for options the command line gives itself are left out, unless the option can be
+/// given more than once (like `--glob`), in which case both apply. So are settings that
+/// conflict with an option on the command line, so a configured `color` doesn't stop `--json`
+/// from being used.
+pub fn merge(
+    command: &Command,
+    matches: &ArgMatches,
+    settings: &[Setting],
+    cli: &[OsString],
+) -> Vec<OsString> {
+    let given: Vec<&Arg> = matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+        .filter_map(|id| command.get_arguments().find(|arg| arg.get_id() == id))
+        .collect();
+    let overridden = |arg: &Arg| {
+        given.iter().any(|&other| {
+            (other == arg && !matches!(arg.get_action(), ArgAction::Append))
+                || command.get_arg_conflicts_with(other).contains(&arg)
+                || command.get_arg_conflicts_with(arg).contains(&other)
+        })
+    };
+
+    let mut args: Vec<OsString> = cli.iter().take(1).cloned().collect();
+    for setting in settings {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == setting.id.as_str());
+        if arg.is_some_and(|arg| !overridden(arg)) {
+            args.extend(setting.args.iter().map(OsString::from));
+        }
+    }
+    args.extend(cli.iter().skip(1).cloned());
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, Parser};
+
+    #[derive(Parser, Debug, PartialEq)]
+    struct TestArgs {
+        #[clap(short = 'n', long)]
+        line_number: bool,
+        #[clap(long)]
+        hidden: bool,
+        #[clap(short = 'C', long)]
+        context: Option<usize>,
+        #[clap(long, default_value = "auto")]
+        color: String,
+        #[clap(long, conflicts_with = "color")]
+        json: bool,
+        #[clap(long)]
+        glob: Vec<String>,
+        #[clap(long)]
+        colors: Option<String>,
+        needle: String,
+    }
+
+    /// Parses `cli` on top of the config files' `contents`, in priority order.
+    fn parse_with(contents: &[&str], cli: &[&str]) -> TestArgs {
+        let command = TestArgs::command();
+        let mut settings: Vec<Setting> = Vec::new();
+        for contents in contents {
+            layer(&mut settings, parse(&command, contents).unwrap());
+        }
+        let cli: Vec<OsString> = std::iter::once("greprs")
+            .chain(cli.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let matches = command.clone().try_get_matches_from(&cli).unwrap();
+        TestArgs::try_parse_from(merge(&command, &matches, &settings, &cli)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let command = TestArgs::command();
+        let settings = parse(
+            &command,
+            "line-number = true\nhidden = false\ncontext = 2\nglob = ['!*.min.js', '*.rs']",
+        )
+        .unwrap();
+        let args: Vec<_> = settings.iter().map(|setting| &setting.args[..]).collect();
+        assert_eq!(
+            args,
+            [
+                &["--context=2".to_string()][..],
+                &["--glob=!*.min.js".to_string(), "--glob=*.rs".to_string()],
+                &[],
+                &["--line-number".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_options() {
+        let command = TestArgs::command();
+        assert!(parse(&command, "lin-number = true").is_err());
+        // Positional arguments don't have a long name to set them by
+        assert!(parse(&command, "needle = 'x'").is_err());
+        assert!(parse(&command, "context = { lines = 2 }").is_err());
+        assert!(parse(&command, "colors = { ms = ['01'] }").is_err());
+        assert!(parse(&command, "not toml").is_err());
+    }
+
+    #[test]
+    fn test_colors_table() {
+        let args = parse_with(&["[colors]\nms = '01;32'\nfn = 34"], &["x"]);
+        assert_eq!(args.colors.as_deref(), Some("fn=34:ms=01;32"));
+        let args = parse_with(&["colors = 'se=2'"], &["x"]);
+        assert_eq!(args.colors.as_deref(), Some("se=2"));
+    }
+
+    #[test]
+    fn test_command_line_wins() {
+        let args = parse_with(&["context = 2\nline-number = true"], &["-C", "5", "x"]);
+        assert_eq!(args.context, Some(5));
+        assert!(args.line_number);
+        assert_eq!(args.needle, "x");
+    }
+
+    #[test]
+    fn test_later_files_win() {
+        let user = "hidden = true\ncontext = 1";
+        let project = "hidden = false";
+        let args = parse_with(&[user, project], &["x"]);
+        assert!(!args.hidden);
+        assert_eq!(args.context, Some(1));
+    }
+
+    #[test]
+    fn test_repeatable_options_accumulate() {
+        let args = parse_with(&["glob = ['!vendor']"], &["--glob", "*.rs", "x"]);
+        assert_eq!(args.glob, vec!["!vendor", "*.rs"]);
+    }
+
+    #[test]
+    fn test_conflicting_settings_are_dropped() {
+        let args = parse_with(&["color = 'always'"], &["--json", "x"]);
+        assert!(args.json);
+        assert_eq!(args.color, "auto");
+        assert_eq!(parse_with(&["color = 'always'"], &["x"]).color, "always");
+    }
+
+    #[test]
+    fn test_project_file_in_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(project_file(&nested), None);
+
+        fs::write(dir.path().join(PROJECT_FILE), "hidden = true").unwrap();
+        assert_eq!(project_file(&nested), Some(dir.path().join(PROJECT_FILE)));
+    }
+
+    #[test]
+    fn test_load_names_the_bad_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PROJECT_FILE);
+        fs::write(&path, "nope = true").unwrap();
+        let error = load(&TestArgs::command(), std::slice::from_ref(&path)).unwrap_err();
+        assert!(format!("{error:#}").contains(&path.display().to_string()));
+    }
+}
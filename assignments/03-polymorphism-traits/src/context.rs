@@ -0,0 +1,197 @@
+use crate::output::{Line, Sink};
+use std::collections::VecDeque;
+use std::io;
+
+/// Tracks the lines around matches so they can be printed as context, like grep's `-A`/`-B`.
+///
+/// Lines that might become leading context wait in a ring buffer holding at most `before` lines.
+/// Once a match is seen they're flushed ahead of it, and the next `after` lines are printed as
+/// trailing context. Windows that touch or overlap merge into a single group.
+pub struct ContextWindow {
+    before: usize,
+    after: usize,
+    /// The number, offset and contents of the most recent unprinted lines, oldest first.
+    buffered: VecDeque<(u64, u64, Vec<u8>)>,
+    /// Evicted line buffers, kept around so the ring doesn't allocate for every line.
+    spare: Vec<Vec<u8>>,
+    after_remaining: usize,
+    last_printed: Option<u64>,
+}
+
+impl ContextWindow {
+    pub fn new(before: usize, after: usize) -> Self {
+        Self {
+            before,
+            after,
+            buffered: VecDeque::with_capacity(before),
+            spare: Vec::new(),
+            after_remaining: 0,
+            last_printed: None,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.before > 0 || self.after > 0
+    }
+
+    /// Prints any buffered leading context for the matching `line`, preceded by a separator if
+    /// the match starts a new group. The caller prints the match itself.
+    pub fn start_match(&mut self, line: &Line, output: &mut dyn Sink) -> io::Result<()> {
+        if self.is_enabled() {
+            let first = self
+                .buffered
+                .front()
+                .map_or(line.number, |buffered| buffered.0);
+            let joins_previous = self.last_printed.is_some_and(|last| first <= last + 1);
+            if !joins_previous {
+                output.write_separator()?;
+            }
+        }
+        while let Some((number, offset, content)) = self.buffered.pop_front() {
+            output.write_context(&Line {
+                path: line.path,
+                number,
+                offset,
+                content: &content,
+            })?;
+            self.spare.push(content);
+        }
+        self.last_printed = Some(line.number);
+        self.after_remaining = self.after;
+        Ok(())
+    }
+
+    /// Whether lines are still being printed as trailing context for the last match.
+    pub fn in_trailing_context(&self) -> bool {
+        self.after_remaining > 0
+    }
+
+    /// Handles a line that didn't match, either printing it as trailing context or holding on to
+    /// it in case it turns out to be leading context.
+    pub fn other_line(&mut self, line: &Line, output: &mut dyn Sink) -> io::Result<()> {
+        if self.after_remaining > 0 {
+            self.after_remaining -= 1;
+            self.last_printed = Some(line.number);
+            return output.write_context(line);
+        }
+        if self.before == 0 {
+            return Ok(());
+        }
+        let mut buffer = if self.buffered.len() == self.before {
+            self.buffered.pop_front().map(|(_, _, content)| content)
+        } else {
+            self.spare.pop()
+        }
+        .unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(line.content);
+        self.buffered.push_back((line.number, line.offset, buffer));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Range;
+
+    /// Renders what it's given in grep's plain format, minus paths. Like the real outputs, it only
+    /// prints separators between groups.
+    #[derive(Default)]
+    struct TextOutput {
+        text: String,
+    }
+
+    impl Sink for TextOutput {
+        fn write_line(&mut self, line: &Line, _matches: &[Range<usize>]) -> io::Result<()> {
+            self.text += &format!("{}\n", String::from_utf8_lossy(line.content));
+            Ok(())
+        }
+
+        fn write_context(&mut self, line: &Line) -> io::Result<()> {
+            self.text += &format!("{}-\n", String::from_utf8_lossy(line.content));
+            Ok(())
+        }
+
+        fn write_separator(&mut self) -> io::Result<()> {
+            if !self.text.is_empty() {
+                self.text += "--\n";
+            }
+            Ok(())
+        }
+    }
+
+    /// Feeds lines 1 to `total` through a window, treating the numbers in `matches` as matches.
+    fn render(before: usize, after: usize, total: u64, matches: &[u64]) -> String {
+        let mut window = ContextWindow::new(before, after);
+        let mut output = TextOutput::default();
+        for number in 1..=total {
+            let content = number.to_string();
+            let line = Line {
+                path: None,
+                number,
+                offset: 0,
+                content: content.as_bytes(),
+            };
+            if matches.contains(&number) {
+                window.start_match(&line, &mut output).unwrap();
+                output.write_line(&line, &[]).unwrap();
+            } else {
+                window.other_line(&line, &mut output).unwrap();
+            }
+        }
+        output.text
+    }
+
+    #[test]
+    fn test_no_context() {
+        assert_eq!(render(0, 0, 10, &[2, 7]), "2\n7\n");
+    }
+
+    #[test]
+    fn test_before_context() {
+        assert_eq!(render(2, 0, 10, &[5]), "3-\n4-\n5\n");
+        // There aren't always enough lines before the match to fill the window
+        assert_eq!(render(3, 0, 10, &[2]), "1-\n2\n");
+    }
+
+    #[test]
+    fn test_after_context() {
+        assert_eq!(render(0, 2, 10, &[5]), "5\n6-\n7-\n");
+        assert_eq!(render(0, 3, 6, &[5]), "5\n6-\n");
+    }
+
+    #[test]
+    fn test_separate_groups() {
+        assert_eq!(render(1, 1, 10, &[2, 8]), "1-\n2\n3-\n--\n7-\n8\n9-\n");
+    }
+
+    #[test]
+    fn test_overlapping_windows_merge() {
+        assert_eq!(render(1, 1, 10, &[3, 5]), "2-\n3\n4-\n5\n6-\n");
+        // Windows that only touch also merge, since no lines are skipped between them
+        assert_eq!(render(1, 1, 10, &[2, 5]), "1-\n2\n3-\n4-\n5\n6-\n");
+    }
+
+    #[test]
+    fn test_in_trailing_context() {
+        let mut window = ContextWindow::new(0, 1);
+        let mut output = TextOutput::default();
+        let line = Line {
+            path: None,
+            number: 1,
+            offset: 0,
+            content: b"match",
+        };
+        assert!(!window.in_trailing_context());
+        window.start_match(&line, &mut output).unwrap();
+        assert!(window.in_trailing_context());
+        window.other_line(&line, &mut output).unwrap();
+        assert!(!window.in_trailing_context());
+    }
+
+    #[test]
+    fn test_consecutive_matches_extend_after_context() {
+        assert_eq!(render(0, 1, 10, &[3, 4]), "3\n4\n5-\n");
+    }
+}
@@ -0,0 +1,383 @@
+//! An on-disk trigram index of a directory tree, so repeated searches of it only have to open
+//! the files that could possibly match.
+
+mod query;
+
+pub use query::{trigrams, Query};
+
+use crate::input::{DecodingInput, FileInput, Source, TextEncoding};
+use crate::walk::{self, WalkOptions};
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What the index is called in the directory it covers.
+pub const INDEX_FILE: &str = ".greprs-index";
+
+/// Marks the start of an index file, and its format version.
+const MAGIC: &[u8; 8] = b"GRPRSIX1";
+
+/// The trigrams in each file under a directory, along with each file's size and modification
+/// time when it was indexed.
+///
+/// A file is only narrowed down by the index while it's the same size and has the same
+/// modification time it had then. Files that have changed since, or were never indexed, are
+/// always searched, so an out of date index makes searches slower but never wrong.
+#[derive(Debug)]
+pub struct Index {
+    /// Where the index file is, which paths in it are relative to.
+    root: PathBuf,
+    files: BTreeMap<PathBuf, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    len: u64,
+    modified: Duration,
+    trigrams: Vec<u32>,
+}
+
+/// What [`Index::update`] did.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Update {
+    /// How many files are in the index now.
+    pub files: usize,
+    /// How many of those had to be read, because they're new or have changed.
+    pub read: usize,
+    /// How many files were dropped from the index because they're gone.
+    pub removed: usize,
+}
+
+impl Index {
+    /// The index covering `dir`: the closest index file in it or one of its parents.
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(INDEX_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// Loads the index for `root` if there is one, or starts an empty one.
+    pub fn open(root: &Path) -> Result<Self> {
+        let root =
+            fs::canonicalize(root).with_context(|| format!("failed to open {}", root.display()))?;
+        let path = root.join(INDEX_FILE);
+        if path.is_file() {
+            return Self::load(&path);
+        }
+        Ok(Self {
+            root,
+            files: BTreeMap::new(),
+        })
+    }
+
+    /// Loads an index file written by [`Index::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let context = || format!("failed to read index {}", path.display());
+        let file = File::open(path).with_context(context)?;
+        let root = fs::canonicalize(path.parent().unwrap_or(Path::new(".")))?;
+        let files = read_entries(&mut BufReader::new(file)).with_context(context)?;
+        Ok(Self { root, files })
+    }
+
+    /// Writes the index to its file in the directory it covers. It's written to a temporary file
+    /// that then replaces the old one, so a search never sees half an index.
+    pub fn save(&self) -> Result<()> {
+        let path = self.root.join(INDEX_FILE);
+        let context = || format!("failed to write index {}", path.display());
+        let mut temp = tempfile::NamedTempFile::new_in(&self.root).with_context(context)?;
+        let mut writer = BufWriter::new(temp.as_file_mut());
+        write_entries(&mut writer, &self.files).with_context(context)?;
+        writer.flush().with_context(context)?;
+        drop(writer);
+        temp.persist(&path).with_context(context)?;
+        Ok(())
+    }
+
+    /// Brings the index up to date with the files under its directory, only reading the ones
+    /// that are new or have changed. Files are walked the way a search walks them, so ignored
+    /// and hidden files are left out. Files that can't be read are left out too, with a warning.
+    pub fn update(&mut self) -> Result<Update> {
+        let mut update = Update::default();
+        let mut files = BTreeMap::new();
+        for file in walk::walk(std::slice::from_ref(&self.root), WalkOptions::default()) {
+            let result = file.and_then(|file| {
+                let relative = file.path.strip_prefix(&self.root)?.to_path_buf();
+                let metadata = fs::metadata(&file.path)?;
+                let (len, modified) = (metadata.len(), modified(&metadata)?);
+                let entry = match self.files.remove(&relative) {
+                    Some(entry) if entry.len == len && entry.modified == modified => entry,
+                    _ => {
+                        update.read += 1;
+                        Entry {
+                            len,
+                            modified,
+                            trigrams: trigrams(&read_contents(&file.path)?),
+                        }
+                    }
+                };
+                files.insert(relative, entry);
+                Ok(())
+            });
+            if let Err(error) = result {
+                eprintln!("greprs: {error:#}");
+            }
+        }
+        update.removed = self.files.len();
+        update.files = files.len();
+        self.files = files;
+        Ok(update)
+    }
+
+    /// Whether the file at `path` could match `query`. Files outside the index's directory, not
+    /// in the index or changed since it was updated could match anything.
+    pub fn may_match(&self, path: &Path, query: &Query) -> bool {
+        let Some(entry) = self.entry(path) else {
+            return true;
+        };
+        let Ok(metadata) = fs::metadata(path) else {
+            return true;
+        };
+        if metadata.len() != entry.len || modified(&metadata).ok() != Some(entry.modified) {
+            return true;
+        }
+        query.matches(&entry.trigrams)
+    }
+
+    fn entry(&self, path: &Path) -> Option<&Entry> {
+        let path = fs::canonicalize(path).ok()?;
+        self.files.get(path.strip_prefix(&self.root).ok()?)
+    }
+}
+
+/// Reads a file the way it's searched by default, decoded if it has a byte order mark, so the
+/// index holds the same bytes matchers see.
+fn read_contents(path: &Path) -> Result<Vec<u8>> {
+    let input = DecodingInput::new(FileInput::new(path), TextEncoding::Auto);
+    let mut contents = Vec::new();
+    input.open()?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+fn modified(metadata: &fs::Metadata) -> Result<Duration> {
+    let modified: SystemTime = metadata.modified()?;
+    // Times before 1970 are unusual enough that always reading those files again is fine
+    Ok(modified.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+// The format is the magic bytes and a count of entries, then for each one its path, size,
+// modification time and trigrams, all little endian. Paths that aren't UTF-8 are left out,
+// which only means those files are always searched.
+fn write_entries(writer: &mut impl Write, files: &BTreeMap<PathBuf, Entry>) -> io::Result<()> {
+    let files: Vec<_> = files
+        .iter()
+        .filter_map(|(path, entry)| Some((path.to_str()?, entry)))
+        .collect();
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(files.len() as u64).to_le_bytes())?;
+    for (path, entry) in files {
+        writer.write_all(&(path.len() as u64).to_le_bytes())?;
+        writer.write_all(path.as_bytes())?;
+        writer.write_all(&entry.len.to_le_bytes())?;
+        writer.write_all(&entry.modified.as_secs().to_le_bytes())?;
+        writer.write_all(&entry.modified.subsec_nanos().to_le_bytes())?;
+        writer.write_all(&(entry.trigrams.len() as u64).to_le_bytes())?;
+        for trigram in &entry.trigrams {
+            writer.write_all(&trigram.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_entries(reader: &mut impl Read) -> Result<BTreeMap<PathBuf, Entry>> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!(
+            "not an index, or one from another version of greprs; run `greprs index build` again"
+        );
+    }
+    let mut files = BTreeMap::new();
+    for _ in 0..read_u64(reader)? {
+        let mut path = vec![0; read_u64(reader)?.try_into()?];
+        reader.read_exact(&mut path)?;
+        let len = read_u64(reader)?;
+        let secs = read_u64(reader)?;
+        let mut nanos = [0; 4];
+        reader.read_exact(&mut nanos)?;
+        let count = read_u64(reader)?;
+        let mut trigrams = Vec::new();
+        for _ in 0..count {
+            let mut trigram = [0; 4];
+            reader.read_exact(&mut trigram)?;
+            trigrams.push(u32::from_le_bytes(trigram));
+        }
+        let entry = Entry {
+            len,
+            modified: Duration::new(secs, u32::from_le_bytes(nanos)),
+            trigrams,
+        };
+        files.insert(PathBuf::from(String::from_utf8(path)?), entry);
+    }
+    Ok(files)
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{Matcher, RegexMatcher};
+
+    /// Sets a file's contents and a modification time of its own, since two writes within the
+    /// filesystem's timestamp resolution could otherwise look like one.
+    fn write(path: &Path, contents: &str, modified_secs: u64) {
+        fs::write(path, contents).unwrap();
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(modified_secs))
+            .unwrap();
+    }
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        write(
+            &dir.path().join("README.md"),
+            "# Widgets\nBuild with cargo.\n",
+            1,
+        );
+        write(
+            &dir.path().join("src/main.rs"),
+            "fn main() {\n    println!(\"Hello, World\");\n}\n",
+            1,
+        );
+        write(
+            &dir.path().join("src/error.rs"),
+            "pub enum Error {\n    NotFound,\n    TimedOut(u64),\n}\n",
+            1,
+        );
+        write(&dir.path().join("notes.txt"), "remember the milk\n", 1);
+        dir
+    }
+
+    /// The files a brute force search with `pattern` matches, and the files the index says
+    /// could, sorted.
+    fn compare(
+        index: &Index,
+        root: &Path,
+        pattern: &str,
+        ignore_case: bool,
+    ) -> (Vec<String>, Vec<String>) {
+        let matcher = RegexMatcher::new(pattern, ignore_case).unwrap();
+        let query = Query::regex(pattern, ignore_case);
+        let (mut matching, mut candidates) = (Vec::new(), Vec::new());
+        for file in walk::walk(&[root.to_path_buf()], WalkOptions::default()) {
+            let path = file.unwrap().path;
+            let name = path.strip_prefix(root).unwrap().display().to_string();
+            if matcher.is_match(&fs::read(&path).unwrap()) {
+                matching.push(name.clone());
+            }
+            if index.may_match(&path, &query) {
+                candidates.push(name);
+            }
+        }
+        (matching, candidates)
+    }
+
+    #[test]
+    fn test_index_agrees_with_brute_force() {
+        let dir = tree();
+        let mut index = Index::open(dir.path()).unwrap();
+        index.update().unwrap();
+        index.save().unwrap();
+        let index = Index::load(&dir.path().join(INDEX_FILE)).unwrap();
+
+        for (pattern, ignore_case, expected) in [
+            ("println", false, &["src/main.rs"][..]),
+            ("hello|milk", true, &["notes.txt", "src/main.rs"]),
+            (r"Timed\w+\(u\d+\)", false, &["src/error.rs"]),
+            (r"\bError\b", false, &["src/error.rs"]),
+            ("widgets", true, &["README.md"]),
+            ("cargo.", false, &["README.md"]),
+            ("nowhere", false, &[]),
+        ] {
+            let (matching, candidates) = compare(&index, dir.path(), pattern, ignore_case);
+            assert_eq!(matching, expected, "{pattern}");
+            // Here the index narrows things down to exactly the files that match
+            assert_eq!(candidates, expected, "{pattern}");
+        }
+
+        // The index ignores case, so it can only narrow things down to a few more files
+        let (matching, candidates) = compare(&index, dir.path(), "hello|milk", false);
+        assert_eq!(matching, ["notes.txt"]);
+        assert_eq!(candidates, ["notes.txt", "src/main.rs"]);
+
+        // Patterns without anything to go on leave every file a candidate
+        let (matching, candidates) = compare(&index, dir.path(), r"\d", false);
+        assert_eq!(matching, ["src/error.rs"]);
+        assert_eq!(candidates.len(), 4);
+    }
+
+    #[test]
+    fn test_update_only_reads_changed_files() {
+        let dir = tree();
+        let mut index = Index::open(dir.path()).unwrap();
+        assert_eq!(
+            index.update().unwrap(),
+            Update {
+                files: 4,
+                read: 4,
+                removed: 0,
+            }
+        );
+        index.save().unwrap();
+
+        // The index file itself is hidden, so it's never indexed
+        let mut index = Index::open(dir.path()).unwrap();
+        assert_eq!(index.update().unwrap().read, 0);
+
+        write(&dir.path().join("notes.txt"), "remember the eggs\n", 2);
+        fs::remove_file(dir.path().join("README.md")).unwrap();
+        write(&dir.path().join("src/lib.rs"), "pub mod error;\n", 2);
+        assert_eq!(
+            index.update().unwrap(),
+            Update {
+                files: 4,
+                read: 2,
+                removed: 1,
+            }
+        );
+        let (_, candidates) = compare(&index, dir.path(), "eggs", false);
+        assert_eq!(candidates, ["notes.txt"]);
+    }
+
+    #[test]
+    fn test_changed_files_are_always_candidates() {
+        let dir = tree();
+        let mut index = Index::open(dir.path()).unwrap();
+        index.update().unwrap();
+
+        let notes = dir.path().join("notes.txt");
+        let query = Query::regex("eggs", false);
+        assert!(!index.may_match(&notes, &query));
+        write(&notes, "remember the eggs\n", 2);
+        assert!(index.may_match(&notes, &query));
+        // As are files the index doesn't know about
+        assert!(index.may_match(Path::new("/elsewhere/notes.txt"), &query));
+    }
+
+    #[test]
+    fn test_load_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(INDEX_FILE);
+        fs::write(&path, "not an index").unwrap();
+        let error = Index::load(&path).unwrap_err();
+        assert!(format!("{error:#}").contains("index build"));
+    }
+}
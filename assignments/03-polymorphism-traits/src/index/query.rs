@@ -0,0 +1,211 @@
+use regex_syntax::hir::{Class, Hir, HirKind};
+use regex_syntax::ParserBuilder;
+
+/// What a file has to contain to possibly match a search, in terms of its trigrams: the runs of
+/// three bytes in it, with ASCII letters lowercased.
+///
+/// Queries only ever narrow down which files are worth searching. A file can satisfy the query
+/// without matching, but a file that matches always satisfies it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// Any file might match.
+    Everything,
+    /// The file has to contain these bytes, which only narrows things down if there are at
+    /// least three of them.
+    Literal(Vec<u8>),
+    All(Vec<Query>),
+    Any(Vec<Query>),
+}
+
+impl Query {
+    /// What a file has to contain for `pattern` to match in it, searched for the way a
+    /// [`RegexMatcher`](crate::matcher::RegexMatcher) would.
+    pub fn regex(pattern: &str, ignore_case: bool) -> Self {
+        match ParserBuilder::new()
+            .case_insensitive(ignore_case)
+            .build()
+            .parse(pattern)
+        {
+            Ok(hir) => Self::from_hir(&hir),
+            Err(_) => Query::Everything,
+        }
+    }
+
+    fn from_hir(hir: &Hir) -> Self {
+        match hir.kind() {
+            // Zero width, so the bytes on either side of it are still next to each other
+            HirKind::Empty | HirKind::Look(_) => Query::Literal(Vec::new()),
+            HirKind::Literal(literal) => Query::Literal(fold(&literal.0)),
+            HirKind::Class(class) => {
+                class_byte(class).map_or(Query::Everything, |byte| Query::Literal(vec![byte]))
+            }
+            HirKind::Repetition(repetition) if repetition.min > 0 => {
+                Self::from_hir(&repetition.sub).without_literal_edges()
+            }
+            HirKind::Repetition(_) => Query::Everything,
+            HirKind::Capture(capture) => Self::from_hir(&capture.sub),
+            HirKind::Concat(hirs) => {
+                // Join runs of literals back up, so trigrams spanning them can be used
+                let mut parts = Vec::new();
+                let mut literal: Option<Vec<u8>> = None;
+                for query in hirs.iter().map(Self::from_hir) {
+                    match query {
+                        Query::Literal(bytes) => literal.get_or_insert_default().extend(bytes),
+                        query => {
+                            parts.extend(literal.take().map(Query::Literal));
+                            parts.push(query);
+                        }
+                    }
+                }
+                parts.extend(literal.map(Query::Literal));
+                Query::All(parts)
+            }
+            HirKind::Alternation(hirs) => Query::Any(hirs.iter().map(Self::from_hir).collect()),
+        }
+    }
+
+    /// A repeated literal can't be joined up with what's around it, since `ab+c` doesn't
+    /// contain `abc`, so hide it from the literal joining in `from_hir`.
+    fn without_literal_edges(self) -> Self {
+        match self {
+            Query::Literal(bytes) => Query::All(vec![Query::Literal(bytes)]),
+            query => query,
+        }
+    }
+
+    /// Whether a file with these trigrams, sorted as [`trigrams`] returns them, could match.
+    pub fn matches(&self, trigrams: &[u32]) -> bool {
+        match self {
+            Query::Everything => true,
+            Query::Literal(bytes) => bytes
+                .windows(3)
+                .all(|window| trigrams.binary_search(&trigram(window)).is_ok()),
+            Query::All(queries) => queries.iter().all(|query| query.matches(trigrams)),
+            Query::Any(queries) => queries.iter().any(|query| query.matches(trigrams)),
+        }
+    }
+}
+
+/// The one byte a class can match once lowercased, like `[Ee]` under `-i`, if there is one.
+/// Classes with non-ASCII members, like the Kelvin sign `-i` puts in with `k`, can't be narrowed
+/// down to a byte.
+fn class_byte(class: &Class) -> Option<u8> {
+    let mut bytes = Vec::new();
+    match class {
+        Class::Unicode(class) => {
+            for range in class.ranges() {
+                for c in range.start()..=range.end() {
+                    bytes.push(u8::try_from(c).ok().filter(u8::is_ascii)?);
+                    if bytes.len() > 2 {
+                        return None;
+                    }
+                }
+            }
+        }
+        Class::Bytes(class) => {
+            for range in class.ranges() {
+                for byte in range.start()..=range.end() {
+                    bytes.push(byte);
+                    if bytes.len() > 2 {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+    let first = bytes.first()?.to_ascii_lowercase();
+    bytes
+        .iter()
+        .all(|byte| byte.to_ascii_lowercase() == first)
+        .then_some(first)
+}
+
+fn fold(bytes: &[u8]) -> Vec<u8> {
+    bytes.to_ascii_lowercase()
+}
+
+fn trigram(window: &[u8]) -> u32 {
+    u32::from(window[0]) << 16 | u32::from(window[1]) << 8 | u32::from(window[2])
+}
+
+/// Every trigram in `contents`, sorted and without duplicates.
+pub fn trigrams(contents: &[u8]) -> Vec<u32> {
+    let contents = fold(contents);
+    let mut trigrams: Vec<u32> = contents.windows(3).map(trigram).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(bytes: &str) -> Query {
+        Query::Literal(bytes.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_literals_are_joined_and_lowercased() {
+        assert_eq!(
+            Query::regex(r"Error: \w+ failed", false),
+            Query::All(vec![
+                literal("error: "),
+                Query::Everything,
+                literal(" failed")
+            ])
+        );
+        // Anchors and word boundaries don't separate what's on either side of them
+        assert_eq!(
+            Query::regex(r"^\bfoo\b$", false),
+            Query::All(vec![literal("foo")])
+        );
+        assert_eq!(
+            Query::regex("warn|error", false),
+            Query::Any(vec![literal("warn"), literal("error")])
+        );
+    }
+
+    #[test]
+    fn test_repetitions() {
+        assert_eq!(
+            Query::regex("ab+c", false),
+            Query::All(vec![
+                literal("a"),
+                Query::All(vec![literal("b")]),
+                literal("c")
+            ])
+        );
+        assert_eq!(
+            Query::regex("x(abc)?y", false),
+            Query::All(vec![literal("x"), Query::Everything, literal("y")])
+        );
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        // Each letter turns into a class of its cases, which lowercases to the same byte
+        assert_eq!(
+            Query::regex("ERROR", true),
+            Query::All(vec![literal("error")])
+        );
+        // Under Unicode case folding, k also matches the Kelvin sign, so it can't be relied on
+        assert_eq!(
+            Query::regex("kit", true),
+            Query::All(vec![Query::Everything, literal("it")])
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let trigrams = trigrams(b"The quick brown fox");
+        assert!(Query::regex("QUICK", true).matches(&trigrams));
+        assert!(Query::regex("quick", false).matches(&trigrams));
+        assert!(!Query::regex("quack", false).matches(&trigrams));
+        assert!(Query::regex("brown|green", false).matches(&trigrams));
+        assert!(!Query::regex("purple|green", false).matches(&trigrams));
+        // Too short to narrow anything down
+        assert!(Query::regex("zz", false).matches(&trigrams));
+        assert!(Query::regex("[", false).matches(&trigrams));
+    }
+}
@@ -0,0 +1,453 @@
+mod follow;
+
+pub use follow::{FollowInput, FollowReader};
+
+use anyhow::{Context, Result};
+use encoding_rs::{Encoding, UTF_8};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A source of lines to search.
+pub trait Source {
+    /// The path to label results with, or `None` for an unnamed stream.
+    fn path(&self) -> Option<&Path>;
+
+    /// Opens the input for reading.
+    fn open(&self) -> Result<Box<dyn BufRead>>;
+
+    /// Maps the whole input into memory so it can be searched without copying, or returns
+    /// `None` if this input can only be streamed.
+    fn map(&self) -> Result<Option<Mmap>> {
+        Ok(None)
+    }
+}
+
+// Lets inputs be wrapped in one another after being chosen at runtime
+impl<I: Source + ?Sized> Source for Box<I> {
+    fn path(&self) -> Option<&Path> {
+        (**self).path()
+    }
+
+    fn open(&self) -> Result<Box<dyn BufRead>> {
+        (**self).open()
+    }
+
+    fn map(&self) -> Result<Option<Mmap>> {
+        (**self).map()
+    }
+}
+
+pub struct FileInput {
+    path: PathBuf,
+}
+
+impl FileInput {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn open_file(&self) -> Result<File> {
+        File::open(&self.path).with_context(|| format!("failed to open {}", self.path.display()))
+    }
+}
+
+impl Source for FileInput {
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn open(&self) -> Result<Box<dyn BufRead>> {
+        Ok(Box::new(BufReader::new(self.open_file()?)))
+    }
+
+    fn map(&self) -> Result<Option<Mmap>> {
+        let file = self.open_file()?;
+        let metadata = file.metadata()?;
+        // Pipes, devices and the like can't be mapped, and mapping an empty file is an error on
+        // some platforms
+        if !metadata.is_file() || metadata.len() == 0 {
+            return Ok(None);
+        }
+        // SAFETY: the map is only read from. If another process truncates the file while we're
+        // searching it we may be killed by SIGBUS, the same tradeoff grep and ripgrep make.
+        let map = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("failed to map {}", self.path.display()))?;
+        Ok(Some(map))
+    }
+}
+
+pub struct StdinInput;
+
+impl Source for StdinInput {
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    fn open(&self) -> Result<Box<dyn BufRead>> {
+        Ok(Box::new(io::stdin().lock()))
+    }
+}
+
+/// Wraps another input, and if its contents turn out to be compressed, searches what they
+/// decompress to instead, as with `--search-zip`. Inputs that aren't compressed are passed
+/// through untouched, so they can still be memory mapped.
+pub struct DecompressingInput<I: Source> {
+    inner: I,
+}
+
+impl<I: Source> DecompressingInput<I> {
+    pub fn new(inner: I) -> Self {
+        Self { inner }
+    }
+}
+
+impl<I: Source> Source for DecompressingInput<I> {
+    fn path(&self) -> Option<&Path> {
+        self.inner.path()
+    }
+
+    fn open(&self) -> Result<Box<dyn BufRead>> {
+        let mut reader = self.inner.open()?;
+        Ok(match Compression::detect(reader.fill_buf()?) {
+            Some(compression) => Box::new(BufReader::new(compression.decoder(reader)?)),
+            None => reader,
+        })
+    }
+
+    fn map(&self) -> Result<Option<Mmap>> {
+        // Compressed data has to be streamed through a decoder
+        Ok(self
+            .inner
+            .map()?
+            .filter(|map| Compression::detect(map).is_none()))
+    }
+}
+
+/// The compression formats `--search-zip` understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Recognizes a compression format from the magic bytes at the start of an input, rather
+    /// than trusting its file extension.
+    pub fn detect(start: &[u8]) -> Option<Self> {
+        const MAGIC: [(&[u8], Compression); 4] = [
+            (b"\x1f\x8b", Compression::Gzip),
+            (b"BZh", Compression::Bzip2),
+            (b"\xfd7zXZ\0", Compression::Xz),
+            (b"\x28\xb5\x2f\xfd", Compression::Zstd),
+        ];
+        MAGIC
+            .iter()
+            .find(|(magic, _)| start.starts_with(magic))
+            .map(|&(_, compression)| compression)
+    }
+
+    /// Wraps `reader` in a decoder for this format. Each decoder carries on through
+    /// concatenated streams, which is what `cat a.gz b.gz` and some log rotators produce.
+    fn decoder(self, reader: Box<dyn BufRead>) -> Result<Box<dyn Read>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+            Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+            Compression::Xz => Box::new(liblzma::bufread::XzDecoder::new_multi_decoder(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        })
+    }
+}
+
+/// Which encoding inputs are read in, as chosen with `--encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding {
+    /// Go by the byte order mark if there is one, and search the raw bytes otherwise.
+    #[default]
+    Auto,
+    Fixed(&'static Encoding),
+}
+
+impl TextEncoding {
+    /// Whether inputs without a byte order mark can be searched exactly as they are.
+    fn is_utf8(self) -> bool {
+        match self {
+            TextEncoding::Auto => true,
+            TextEncoding::Fixed(encoding) => encoding == UTF_8,
+        }
+    }
+}
+
+impl FromStr for TextEncoding {
+    type Err = String;
+
+    /// Accepts `auto` or any WHATWG encoding label, like `utf-16le`, `latin1` or `shift_jis`.
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        if label.eq_ignore_ascii_case("auto") {
+            return Ok(TextEncoding::Auto);
+        }
+        Encoding::for_label(label.as_bytes())
+            .map(TextEncoding::Fixed)
+            .ok_or_else(|| format!("unknown encoding {label:?}"))
+    }
+}
+
+/// Wraps another input and transcodes it to UTF-8 before it's searched, so needles match no
+/// matter how the text was stored. A UTF-8 or UTF-16 byte order mark takes precedence over the
+/// chosen encoding. Bytes that aren't valid in the encoding become U+FFFD instead of failing the
+/// search.
+pub struct DecodingInput<I: Source> {
+    inner: I,
+    encoding: TextEncoding,
+}
+
+impl<I: Source> DecodingInput<I> {
+    pub fn new(inner: I, encoding: TextEncoding) -> Self {
+        Self { inner, encoding }
+    }
+}
+
+impl<I: Source> Source for DecodingInput<I> {
+    fn path(&self) -> Option<&Path> {
+        self.inner.path()
+    }
+
+    fn open(&self) -> Result<Box<dyn BufRead>> {
+        let encoding = match self.encoding {
+            TextEncoding::Auto => None,
+            TextEncoding::Fixed(encoding) => Some(encoding),
+        };
+        let decoder = DecodeReaderBytesBuilder::new()
+            .encoding(encoding)
+            .bom_override(true)
+            .strip_bom(true)
+            // UTF-8 is searched as is, invalid bytes and all. Only turned on when UTF-8 is wanted
+            // anyway, since it would also keep a UTF-8 byte order mark from overriding the
+            // chosen encoding.
+            .utf8_passthru(self.encoding.is_utf8())
+            .build(self.inner.open()?);
+        Ok(Box::new(BufReader::new(decoder)))
+    }
+
+    fn map(&self) -> Result<Option<Mmap>> {
+        if !self.encoding.is_utf8() {
+            return Ok(None);
+        }
+        Ok(self
+            .inner
+            .map()?
+            .filter(|map| Encoding::for_bom(map).is_none()))
+    }
+}
+
+/// How much of the start of an input to look at when guessing whether it's binary.
+const BINARY_PEEK_LEN: usize = 8 * 1024;
+
+/// Guesses whether `start`, the beginning of an input, comes from a binary file the same way grep
+/// does: by looking for a NUL byte in the first block.
+pub fn looks_binary(start: &[u8]) -> bool {
+    let block = &start[..start.len().min(BINARY_PEEK_LEN)];
+    memchr::memchr(0, block).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_file_input() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "first\nsecond\n").unwrap();
+
+        let input = FileInput::new(file.path());
+        assert_eq!(input.path(), Some(file.path()));
+
+        let mut contents = String::new();
+        input.open().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "first\nsecond\n");
+    }
+
+    #[test]
+    fn test_file_input_map() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let input = FileInput::new(file.path());
+        assert!(input.map().unwrap().is_none());
+
+        file.write_all(b"mapped\n").unwrap();
+        let map = input.map().unwrap().unwrap();
+        assert_eq!(&map[..], b"mapped\n");
+    }
+
+    #[test]
+    fn test_stdin_input_is_not_mapped() {
+        assert!(StdinInput.map().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_file_input_missing() {
+        let input = FileInput::new("does/not/exist.txt");
+        let error = input.open().err().unwrap();
+        assert!(error.to_string().contains("does/not/exist.txt"));
+    }
+
+    #[test]
+    fn test_stdin_input_has_no_path() {
+        assert_eq!(StdinInput.path(), None);
+    }
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Xz => {
+                let mut encoder = liblzma::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Zstd => zstd::encode_all(data, 0).unwrap(),
+        }
+    }
+
+    fn read_all(input: &impl Source) -> Vec<u8> {
+        let mut contents = Vec::new();
+        input.open().unwrap().read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_decompressing_input() {
+        let text = b"first line\nsecond line\n";
+        for compression in [
+            Compression::Gzip,
+            Compression::Bzip2,
+            Compression::Xz,
+            Compression::Zstd,
+        ] {
+            let compressed = compress(compression, text);
+            assert_eq!(Compression::detect(&compressed), Some(compression));
+
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(&compressed).unwrap();
+            let input = DecompressingInput::new(FileInput::new(file.path()));
+            assert_eq!(input.path(), Some(file.path()));
+            assert_eq!(read_all(&input), text, "{compression:?}");
+            assert!(input.map().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_decompressing_input_reads_concatenated_streams() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&compress(Compression::Gzip, b"rotated\n"))
+            .unwrap();
+        file.write_all(&compress(Compression::Gzip, b"appended\n"))
+            .unwrap();
+        let input = DecompressingInput::new(FileInput::new(file.path()));
+        assert_eq!(read_all(&input), b"rotated\nappended\n");
+    }
+
+    #[test]
+    fn test_decompressing_input_passes_plain_files_through() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"not compressed\n").unwrap();
+        let input = DecompressingInput::new(FileInput::new(file.path()));
+        assert_eq!(Compression::detect(b"not compressed\n"), None);
+        assert_eq!(read_all(&input), b"not compressed\n");
+        assert_eq!(&input.map().unwrap().unwrap()[..], b"not compressed\n");
+    }
+
+    #[test]
+    fn test_decompressing_input_reports_corrupt_data() {
+        let mut compressed = compress(Compression::Gzip, b"some text\n");
+        compressed.truncate(compressed.len() / 2);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&compressed).unwrap();
+        let input = DecompressingInput::new(FileInput::new(file.path()));
+        let mut contents = Vec::new();
+        assert!(input.open().unwrap().read_to_end(&mut contents).is_err());
+    }
+
+    fn decode(encoding: TextEncoding, contents: &[u8]) -> (Vec<u8>, bool) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents).unwrap();
+        let input = DecodingInput::new(FileInput::new(file.path()), encoding);
+        (read_all(&input), input.map().unwrap().is_some())
+    }
+
+    #[test]
+    fn test_decoding_input_detects_byte_order_marks() {
+        let (decoded, mapped) = decode(TextEncoding::Auto, b"\xff\xfeh\0i\0\n\0");
+        assert_eq!(decoded, b"hi\n");
+        assert!(!mapped);
+
+        let (decoded, _) = decode(TextEncoding::Auto, b"\xfe\xff\0h\0i\0\n");
+        assert_eq!(decoded, b"hi\n");
+
+        // The byte order mark wins over whatever was asked for
+        let latin1 = "latin1".parse().unwrap();
+        let (decoded, _) = decode(latin1, "\u{feff}café\n".as_bytes());
+        assert_eq!(decoded, "café\n".as_bytes());
+    }
+
+    #[test]
+    fn test_decoding_input_with_fixed_encoding() {
+        let (decoded, mapped) = decode("latin1".parse().unwrap(), b"caf\xe9\n");
+        assert_eq!(decoded, "café\n".as_bytes());
+        assert!(!mapped);
+
+        let (decoded, _) = decode("utf-16le".parse().unwrap(), b"o\0k\0");
+        assert_eq!(decoded, b"ok");
+
+        // An unpaired surrogate can't be decoded, but doesn't stop the rest of the input
+        let (decoded, _) = decode("utf-16le".parse().unwrap(), b"a\0\x00\xd8b\0");
+        assert_eq!(decoded, "a\u{fffd}b".as_bytes());
+    }
+
+    #[test]
+    fn test_decoding_input_leaves_utf8_alone() {
+        let (decoded, mapped) = decode(TextEncoding::Auto, b"caf\xe9 \xff\n");
+        assert_eq!(decoded, b"caf\xe9 \xff\n");
+        assert!(mapped);
+
+        let (decoded, mapped) = decode("utf-8".parse().unwrap(), b"plain\n");
+        assert_eq!(decoded, b"plain\n");
+        assert!(mapped);
+    }
+
+    #[test]
+    fn test_text_encoding_labels() {
+        assert_eq!("auto".parse(), Ok(TextEncoding::Auto));
+        assert_eq!(
+            "UTF-16BE".parse(),
+            Ok(TextEncoding::Fixed(encoding_rs::UTF_16BE))
+        );
+        assert!("klingon".parse::<TextEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_looks_binary() {
+        assert!(!looks_binary(b"plain text\n"));
+        assert!(looks_binary(b"\x7fELF\x02\x01\x01\0\0\0"));
+        assert!(!looks_binary(b""));
+
+        let mut late_nul = vec![b'a'; BINARY_PEEK_LEN];
+        late_nul.push(0);
+        assert!(!looks_binary(&late_nul));
+    }
+}
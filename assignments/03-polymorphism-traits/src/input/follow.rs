@@ -0,0 +1,247 @@
+use super::Source;
+use anyhow::{Context, Result};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before looking for more of a followed file after reaching its end.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A file to search as it grows, like `tail -F` does, for `--follow`.
+///
+/// It can't be memory mapped, since it isn't finished yet. Searches of it run until `stop` is
+/// set, which without anything else to set it means forever.
+pub struct FollowInput {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+}
+
+impl FollowInput {
+    pub fn new(path: impl Into<PathBuf>, stop: Arc<AtomicBool>) -> Self {
+        Self {
+            path: path.into(),
+            stop,
+        }
+    }
+}
+
+impl Source for FollowInput {
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn open(&self) -> Result<Box<dyn BufRead>> {
+        Ok(Box::new(FollowReader::open(&self.path, self.stop.clone())?))
+    }
+}
+
+/// Reads a file that's still being written to. At the end of the file it waits for more instead
+/// of returning end of file, so a line that's only half written yet is read once it's finished.
+///
+/// While waiting it keeps an eye on the path it was opened from. If the file there is replaced,
+/// as when a log is rotated, whatever's left of the old one is read and then the new one is read
+/// from the start. If the file shrinks, as when a log is truncated, it's read again from the
+/// start too.
+pub struct FollowReader {
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// Which file `reader` is reading, to tell when it's been replaced.
+    id: Option<FileId>,
+    /// How far into the file `reader` has got, to tell when it's been truncated.
+    position: u64,
+    stop: Arc<AtomicBool>,
+}
+
+impl FollowReader {
+    /// Opens `path` to be followed until `stop` is set. Reads end once `stop` is set and
+    /// everything written so far has been read.
+    pub fn open(path: impl Into<PathBuf>, stop: Arc<AtomicBool>) -> Result<Self> {
+        let path = path.into();
+        let file = open_file(&path)?;
+        Ok(Self {
+            id: file_id(&file.metadata()?),
+            reader: BufReader::new(file),
+            path,
+            position: 0,
+            stop,
+        })
+    }
+
+    /// Starts reading the file at the path again, if it's been replaced or truncated since it
+    /// was opened. Returns whether there's any chance of more to read.
+    fn reopen_if_changed(&mut self) -> io::Result<bool> {
+        // The file may have gone for a moment in the middle of being rotated
+        let Ok(metadata) = fs::metadata(&self.path) else {
+            return Ok(false);
+        };
+        if file_id(&metadata) != self.id {
+            // Anything written to the old file just before it was replaced still counts
+            if !self.reader.fill_buf()?.is_empty() {
+                return Ok(true);
+            }
+            let Ok(file) = File::open(&self.path) else {
+                return Ok(false);
+            };
+            self.id = file_id(&file.metadata()?);
+            self.reader = BufReader::new(file);
+            self.position = 0;
+            return Ok(true);
+        }
+        if metadata.len() < self.position {
+            self.reader.rewind()?;
+            self.position = 0;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for FollowReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        loop {
+            // Check first and then fill again, since the buffer can't be returned from inside
+            // the loop. The second fill reads nothing new, it just returns what's buffered.
+            if !self.reader.fill_buf()?.is_empty() {
+                return self.reader.fill_buf();
+            }
+            if self.reopen_if_changed()? {
+                continue;
+            }
+            // Only stop at the end of the file, so everything written before then is searched
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(&[]);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount);
+        self.position += amount as u64;
+    }
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    File::open(path).with_context(|| format!("failed to open {}", path.display()))
+}
+
+/// Identifies a file independently of its path: its device and inode numbers.
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Without inodes only truncation can be noticed, not replacement
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<FileId> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::LiteralMatcher;
+    use crate::output::{Line, Sink};
+    use crate::search::Searcher;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::ops::Range;
+    use std::sync::mpsc::{self, Sender};
+
+    /// Sends every selected line to the test as soon as it's found.
+    struct Lines(Sender<String>);
+
+    impl Sink for Lines {
+        fn write_line(&mut self, line: &Line, _matches: &[Range<usize>]) -> io::Result<()> {
+            let line = String::from_utf8_lossy(line.content).into_owned();
+            self.0.send(line).map_err(io::Error::other)
+        }
+
+        fn write_context(&mut self, _line: &Line) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn write_separator(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn append(path: &Path, contents: &str) {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_follow_appends_rotation_and_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        append(&path, "needle 1\nhay\n");
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let input = FollowInput::new(&path, stop.clone());
+        assert!(input.map().unwrap().is_none());
+        let (sender, receiver) = mpsc::channel();
+        let search = thread::spawn(move || {
+            let matcher = LiteralMatcher::new("needle", false).unwrap();
+            Searcher::default()
+                .search_source(&matcher, &input, &mut Lines(sender))
+                .unwrap()
+        });
+        let next = || receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        assert_eq!(next(), "needle 1");
+        // A line is only searched once it's finished
+        append(&path, "hay\nneed");
+        thread::sleep(POLL_INTERVAL * 2);
+        append(&path, "le 2\n");
+        assert_eq!(next(), "needle 2");
+
+        // Rotated: the last line of the old file still counts, then the new one is followed
+        let rotated = dir.path().join("app.log.1");
+        append(&path, "needle 3\n");
+        fs::rename(&path, &rotated).unwrap();
+        append(&path, "needle 4\n");
+        assert_eq!(next(), "needle 3");
+        assert_eq!(next(), "needle 4");
+        append(&rotated, "needle in the old file\n");
+
+        // Truncated, which can only be noticed when the file ends up shorter than before
+        fs::write(&path, "needle5\n").unwrap();
+        assert_eq!(next(), "needle5");
+
+        append(&path, "needle 6\n");
+        stop.store(true, Ordering::Relaxed);
+        let stats = search.join().unwrap();
+        assert_eq!(next(), "needle 6");
+        assert_eq!(stats.matched_lines, 6);
+    }
+
+    #[test]
+    fn test_follow_missing_file() {
+        let stop = Arc::new(AtomicBool::new(true));
+        let input = FollowInput::new("does/not/exist.log", stop);
+        let error = input.open().err().unwrap();
+        assert!(error.to_string().contains("does/not/exist.log"));
+    }
+}
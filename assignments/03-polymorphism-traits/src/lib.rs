@@ -0,0 +1,66 @@
+//! The search engine behind the `greprs` command, for embedding grep-style searches in other
+//! tools without shelling out.
+//!
+//! A search has three parts, each behind a trait so it can be swapped out:
+//!
+//! - A [`Source`] supplies the bytes to search, like a [`FileInput`](input::FileInput) or stdin,
+//!   possibly wrapped to decompress or decode them.
+//! - A [`Matcher`] finds the needle in a line, like a [`LiteralMatcher`](matcher::LiteralMatcher)
+//!   or a [`RegexMatcher`](matcher::RegexMatcher).
+//! - A [`Sink`] receives what was found: selected lines with where they matched, context lines
+//!   and per-input [`Stats`]. The printers `greprs` itself uses live in [`output`].
+//!
+//! A [`Searcher`] drives a matcher over a source and pushes events into a sink:
+//!
+//! ```
+//! use greprs::matcher::LiteralMatcher;
+//! use greprs::{Line, Searcher, Sink};
+//! use std::io;
+//! use std::ops::Range;
+//!
+//! /// Collects the line numbers of every match.
+//! #[derive(Default)]
+//! struct LineNumbers(Vec<u64>);
+//!
+//! impl Sink for LineNumbers {
+//!     fn write_line(&mut self, line: &Line, _matches: &[Range<usize>]) -> io::Result<()> {
+//!         self.0.push(line.number);
+//!         Ok(())
+//!     }
+//!
+//!     fn write_context(&mut self, _line: &Line) -> io::Result<()> {
+//!         Ok(())
+//!     }
+//!
+//!     fn write_separator(&mut self) -> io::Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let matcher = LiteralMatcher::new("error", true)?;
+//! let mut sink = LineNumbers::default();
+//! let stats = Searcher::default().search_slice(
+//!     &matcher,
+//!     b"ok\nERROR: disk full\nok\nerror: retrying\n",
+//!     None,
+//!     &mut sink,
+//! )?;
+//! assert_eq!(sink.0, [2, 4]);
+//! assert_eq!(stats.matched_lines, 2);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+mod context;
+pub mod index;
+pub mod input;
+pub mod matcher;
+pub mod output;
+pub mod parallel;
+pub mod replace;
+pub mod search;
+pub mod walk;
+
+pub use input::Source;
+pub use matcher::Matcher;
+pub use output::{Line, Sink};
+pub use search::{Searcher, Stats};
@@ -1,29 +1,1063 @@
-use anyhow::Result;
-use clap::Parser;
-use colored::Color;
-use std::path::PathBuf;
+mod config;
+mod tui;
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use greprs::index::{Index, Query};
+use greprs::input::{
+    self, DecodingInput, DecompressingInput, FileInput, FollowInput, Source, StdinInput,
+    TextEncoding,
+};
+use greprs::matcher::{
+    self, AllOfMatcher, Boundary, Column, Field, FieldMatcher, FuzzyMatcher, LiteralMatcher,
+    Matcher, PcreMatcher, RegexMatcher,
+};
+use greprs::output::{
+    ColorChoice, ColoredOutput, CountOutput, FileListOutput, JsonOutput, PlainOutput, PrintOptions,
+    Sink, TallyingOutput, Theme,
+};
+use greprs::parallel;
+use greprs::replace::{self, Template};
+use greprs::search::{BinaryFiles, Searcher, Stats};
+use greprs::walk::{self, FilterOptions, PathFilter, WalkOptions, WalkedFile};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, BufWriter, IsTerminal, LineWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
 
 #[derive(Parser, Debug)]
+#[clap(
+    after_help = "Run `greprs index build [DIR]` to build the index that --indexed searches with."
+)]
 struct Args {
     #[clap(short, long)]
     ignore_case: bool,
 
+    /// Ignore case unless a pattern contains an uppercase letter
+    #[clap(short = 'S', long)]
+    smart_case: bool,
+
+    /// Only match whole words
+    #[clap(short = 'w', long, conflicts_with = "fuzzy")]
+    word_regexp: bool,
+
+    /// Only match whole lines
+    #[clap(short = 'x', long, conflicts_with_all = ["fuzzy", "word_regexp"])]
+    line_regexp: bool,
+
     #[clap(short = 'v', long)]
     invert_match: bool,
 
     #[clap(short, long)]
     regex: bool,
 
-    #[clap(short, long)]
-    color: Option<Color>,
+    /// Search for Perl-style regexes, with look-around and backreferences, using a backtracking
+    /// engine. Lines that take it too long to search are skipped with a warning
+    #[clap(short = 'P', long, conflicts_with = "fuzzy")]
+    pcre: bool,
+
+    /// Match text within DISTANCE edits (insertions, deletions or substitutions) of the needle
+    #[clap(long, value_name = "DISTANCE", conflicts_with = "regex")]
+    fuzzy: Option<usize>,
+
+    /// A pattern to search for. Give it more than once to match lines containing any of them
+    #[clap(short = 'e', long = "regexp", value_name = "PATTERN")]
+    patterns: Vec<String>,
+
+    /// Read patterns to search for from FILE, one per line
+    #[clap(short = 'f', long = "file", value_name = "FILE")]
+    pattern_files: Vec<PathBuf>,
+
+    /// Only match lines containing every pattern, instead of any of them
+    #[clap(long)]
+    all_of: bool,
+
+    /// Let matches span lines, by searching whole files at once. Every line a match touches is
+    /// printed
+    #[clap(short = 'U', long, conflicts_with_all = ["all_of", "replace"])]
+    multiline: bool,
+
+    /// When to color output: auto (only on a terminal), always or never. A color name colors
+    /// only matches, in that color. Otherwise colors are taken from GREP_COLORS, then
+    /// GREPRS_COLORS
+    #[clap(short, long, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
+
+    /// Colors to use, in GREP_COLORS format (like ms=01;32:fn=34), over those from the
+    /// environment. In a config file, this can be a [colors] table of the same keys
+    #[clap(long, value_name = "SPEC")]
+    colors: Option<String>,
+
+    /// Print how many lines matched in each file instead of the lines themselves
+    #[clap(long, conflicts_with_all = ["files_with_matches", "files_without_match"])]
+    count: bool,
+
+    /// Only print the paths of files with at least one match
+    #[clap(short = 'l', long, conflicts_with = "files_without_match")]
+    files_with_matches: bool,
+
+    /// Only print the paths of files without any matches
+    #[clap(short = 'L', long)]
+    files_without_match: bool,
+
+    /// Print only the matched parts of each line, one per line
+    #[clap(short = 'o', long)]
+    only_matching: bool,
+
+    /// Print the line number of each line
+    #[clap(short = 'n', long)]
+    line_number: bool,
+
+    /// Print the column of the first match on each line. Implies --line-number
+    #[clap(long)]
+    column: bool,
+
+    /// Print the byte offset of each line, or of each match with -o
+    #[clap(short = 'b', long)]
+    byte_offset: bool,
+
+    /// Stop searching each file after NUM matching lines
+    #[clap(short = 'm', long, value_name = "NUM")]
+    max_count: Option<u64>,
+
+    /// Print results as JSON Lines, one message per line, for other programs to read
+    #[clap(
+        long,
+        conflicts_with_all = ["color", "count", "files_with_matches", "files_without_match"]
+    )]
+    json: bool,
+
+    /// Print lines with every match replaced by TEMPLATE, which can refer to capture groups as
+    /// $1 or ${name}
+    #[clap(long, value_name = "TEMPLATE")]
+    replace: Option<String>,
+
+    /// Rewrite files with the replacements instead of printing them. Give a SUFFIX, as in
+    /// --in-place=.bak, to keep a copy of each original
+    #[clap(
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        requires = "replace",
+        conflicts_with_all = ["invert_match", "json", "search_zip", "encoding"]
+    )]
+    in_place: Option<String>,
+
+    /// Only search files of TYPE, such as rust or md. Give it more than once for several types
+    #[clap(short = 't', long = "type", value_name = "TYPE")]
+    types: Vec<String>,
+
+    /// Don't search files of TYPE
+    #[clap(short = 'T', long = "type-not", value_name = "TYPE")]
+    types_not: Vec<String>,
+
+    /// Define a file type as NAME:GLOB, like web:*.{html,css}, or add a glob to an existing one.
+    /// Handy in a config file
+    #[clap(long = "type-add", value_name = "NAME:GLOB")]
+    type_definitions: Vec<String>,
+
+    /// Only search paths matching GLOB, or skip them if it starts with !, as in --glob '!target/'.
+    /// Later globs take precedence
+    #[clap(short = 'g', long = "glob", value_name = "GLOB")]
+    globs: Vec<String>,
+
+    /// Browse results in a terminal UI, with a preview of each match, searching again as the
+    /// needle is edited. Enter opens the selected match in $EDITOR
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "patterns", "pattern_files", "json", "count", "files_with_matches",
+            "files_without_match", "in_place"
+        ]
+    )]
+    tui: bool,
+
+    /// Treat each line as a JSON record and only search the value at PATH, like request.method or
+    /// tags.0. Whole records are still printed
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["csv_column", "multiline", "in_place"]
+    )]
+    jsonl_field: Option<String>,
+
+    /// Treat each line as a CSV record and only search COLUMN, given by its name in the first
+    /// line or by its number, counting from 1. Whole records are still printed
+    #[clap(long, value_name = "COLUMN", conflicts_with_all = ["multiline", "in_place"])]
+    csv_column: Option<Column>,
+
+    /// Keep searching FILE as it grows, like tail -f, printing matches as they're appended.
+    /// Follows the log to its new file when it's rotated, and from the start when it's truncated
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "multiline", "count", "files_with_matches", "files_without_match", "in_place", "tui"
+        ]
+    )]
+    follow: bool,
+
+    /// Search hidden files and directories
+    #[clap(long)]
+    hidden: bool,
+
+    /// Don't respect .gitignore and .ignore files
+    #[clap(long)]
+    no_ignore: bool,
+
+    /// Search binary files found in directories instead of skipping them. How they're searched
+    /// is up to --binary-files
+    #[clap(long)]
+    binary: bool,
+
+    /// How to search files with a NUL byte near the start: report that they match (binary),
+    /// search them like any other file (text) or treat them as not matching (without-match)
+    #[clap(long, value_name = "TYPE", default_value = "binary")]
+    binary_files: BinaryFiles,
+
+    /// Search binary files as if they were text, like --binary-files=text
+    #[clap(short = 'a', long, conflicts_with = "binary_files")]
+    text: bool,
+
+    /// Search inside gzip, bzip2, xz and zstd compressed files, recognized by their contents
+    #[clap(short = 'z', long)]
+    search_zip: bool,
+
+    /// Read inputs in ENCODING, such as utf-16le or latin1, instead of going by their byte order
+    /// mark
+    #[clap(long, value_name = "ENCODING", default_value = "auto")]
+    encoding: TextEncoding,
+
+    /// Only search the files that the index made by `greprs index build` says could match.
+    /// Files that have changed since the index was last built are always searched
+    #[clap(long, conflicts_with_all = ["search_zip", "encoding", "follow", "tui"])]
+    indexed: bool,
+
+    /// Always read files as streams instead of memory mapping them
+    #[clap(long)]
+    no_mmap: bool,
+
+    /// Ignore ~/.config/greprs/config.toml and .greprs.toml
+    #[clap(long)]
+    no_config: bool,
+
+    /// Number of files to search at once. Defaults to the number of CPUs
+    #[clap(short = 'j', long, value_name = "NUM")]
+    threads: Option<NonZeroUsize>,
+
+    /// Print NUM lines of trailing context after each match
+    #[clap(short = 'A', long, value_name = "NUM")]
+    after_context: Option<usize>,
+
+    /// Print NUM lines of leading context before each match
+    #[clap(short = 'B', long, value_name = "NUM")]
+    before_context: Option<usize>,
+
+    /// Print NUM lines of context on both sides of each match
+    #[clap(short = 'C', long, value_name = "NUM")]
+    context: Option<usize>,
+
+    /// The pattern to search for, unless patterns were given with -e or -f
+    #[clap(required_unless_present_any = ["patterns", "pattern_files"])]
+    needle: Option<String>,
+
+    /// Files or directories to search, recursively. Reads stdin if none are given
+    files: Vec<PathBuf>,
+}
+
+/// What greprs can do besides searching. These are parsed apart from searches, and only when
+/// `index` is followed by one of its own subcommands, so `greprs index src/` still searches for
+/// "index".
+#[derive(Parser, Debug)]
+#[clap(name = "greprs")]
+enum Command {
+    /// Manage the trigram index that --indexed searches with
+    #[clap(subcommand)]
+    Index(IndexCommand),
+}
+
+impl Command {
+    /// The subcommand that `cli` runs, if it doesn't run a search.
+    fn parse_from_cli(cli: &[OsString]) -> Option<Self> {
+        let command = Command::command();
+        let index = command.find_subcommand("index")?;
+        let runs_index = cli.get(1).is_some_and(|arg| arg == "index")
+            && cli
+                .get(2)
+                .is_some_and(|arg| index.find_subcommand(arg).is_some());
+        runs_index.then(|| Command::parse_from(cli))
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum IndexCommand {
+    /// Index the files under DIR, or bring an existing index up to date by reading only the
+    /// files that have changed since
+    Build {
+        #[clap(default_value = ".")]
+        dir: PathBuf,
+    },
+}
+
+impl Args {
+    /// Parses `cli` over the defaults set in config files, unless it says not to.
+    fn parse_with_config(cli: &[OsString]) -> Result<Self> {
+        let command = Args::command();
+        let matches = command.clone().get_matches_from(cli);
+        if matches.get_flag("no_config") {
+            return Ok(Args::from_arg_matches(&matches)?);
+        }
+        let settings = config::load(&command, &config::find_files(&env::current_dir()?))?;
+        Ok(Args::parse_from(config::merge(
+            &command, &matches, &settings, cli,
+        )))
+    }
+
+    /// Collects every pattern to search for. Like grep, once patterns are given with -e or -f the
+    /// first positional argument is a file to search rather than the needle.
+    fn take_patterns(&mut self) -> Result<Vec<String>> {
+        if self.patterns.is_empty() && self.pattern_files.is_empty() {
+            return Ok(self.needle.take().into_iter().collect());
+        }
+        let mut patterns = std::mem::take(&mut self.patterns);
+        for path in &self.pattern_files {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("failed to read patterns from {}", path.display()))?;
+            patterns.extend(contents.lines().map(String::from));
+        }
+        if let Some(file) = self.needle.take() {
+            self.files.insert(0, file.into());
+        }
+        Ok(patterns)
+    }
+
+    /// Whether to ignore case when searching for `patterns`, going by -i and -S.
+    fn ignore_case(&self, patterns: &[String]) -> bool {
+        self.ignore_case
+            || (self.smart_case
+                && !patterns
+                    .iter()
+                    .any(|pattern| matcher::has_uppercase(pattern, self.is_regex())))
+    }
 
-    needle: String,
+    fn build_matcher(&self, patterns: &[String]) -> Result<Box<dyn Matcher>> {
+        let ignore_case = self.ignore_case(patterns);
+        if self.all_of {
+            let matchers = patterns
+                .iter()
+                .map(|pattern| self.build_pattern_matcher(pattern, ignore_case))
+                .collect::<Result<_>>()?;
+            return Ok(Box::new(AllOfMatcher::new(matchers)));
+        }
+        let regexes: Option<Vec<_>> = patterns
+            .iter()
+            .map(|pattern| self.regex_for(pattern))
+            .collect();
+        Ok(if let Some(distance) = self.fuzzy {
+            Box::new(FuzzyMatcher::any_of(patterns, distance, ignore_case))
+        } else if let Some(regexes) = regexes {
+            if self.pcre {
+                Box::new(PcreMatcher::any_of(&regexes, ignore_case)?)
+            } else {
+                Box::new(regex_matcher(&regexes, ignore_case)?)
+            }
+        } else {
+            Box::new(LiteralMatcher::any_of(patterns, ignore_case)?)
+        })
+    }
 
-    file: Option<PathBuf>,
+    fn build_pattern_matcher(&self, pattern: &str, ignore_case: bool) -> Result<Box<dyn Matcher>> {
+        Ok(if let Some(distance) = self.fuzzy {
+            Box::new(FuzzyMatcher::new(pattern, distance, ignore_case))
+        } else if let Some(regex) = self.regex_for(pattern) {
+            if self.pcre {
+                Box::new(PcreMatcher::new(&regex, ignore_case)?)
+            } else {
+                Box::new(regex_matcher(&[regex], ignore_case)?)
+            }
+        } else {
+            Box::new(LiteralMatcher::new(pattern, ignore_case)?)
+        })
+    }
+
+    /// What a file has to contain to possibly match `patterns`, for narrowing a search down with
+    /// the index.
+    fn index_query(&self, patterns: &[String]) -> Query {
+        // Inverted searches and -L are after the files without matches, fuzzy matches can look
+        // like anything, and the index doesn't understand Perl-style regexes
+        if self.invert_match || self.files_without_match || self.fuzzy.is_some() || self.pcre {
+            return Query::Everything;
+        }
+        let ignore_case = self.ignore_case(patterns);
+        let queries = patterns
+            .iter()
+            .map(|pattern| {
+                let regex = self
+                    .regex_for(pattern)
+                    .unwrap_or_else(|| regex::escape(pattern));
+                Query::regex(&regex, ignore_case)
+            })
+            .collect();
+        if self.all_of {
+            Query::All(queries)
+        } else {
+            Query::Any(queries)
+        }
+    }
+
+    /// Whether patterns are regexes rather than plain strings, as with -r or -P.
+    fn is_regex(&self) -> bool {
+        self.regex || self.pcre
+    }
+
+    /// The regex to search for `pattern` with, or `None` if it can be found as a plain string.
+    fn regex_for(&self, pattern: &str) -> Option<String> {
+        let boundary = if self.line_regexp {
+            Boundary::Line
+        } else if self.word_regexp {
+            Boundary::Word
+        } else {
+            Boundary::None
+        };
+        if self.is_regex() {
+            Some(boundary.wrap(pattern))
+        } else if boundary != Boundary::None {
+            Some(boundary.wrap(&regex::escape(pattern)))
+        } else {
+            None
+        }
+    }
+
+    /// The part of each record to search, if it's only part, going by `start`, the beginning of
+    /// an input, for the names of CSV columns.
+    fn field(&self, start: &[u8]) -> Result<Option<Field>> {
+        if let Some(path) = &self.jsonl_field {
+            return Ok(Some(Field::json(path)));
+        }
+        let Some(column) = &self.csv_column else {
+            return Ok(None);
+        };
+        // An empty input has no header, but no records to search either
+        if start.is_empty() {
+            return Ok(None);
+        }
+        let header = start.split(|&byte| byte == b'\n').next().unwrap_or(start);
+        match (column.resolve(header), column) {
+            (Some(index), _) => Ok(Some(Field::Csv(index))),
+            (None, Column::Name(name)) => bail!("no column named {name:?} in the first line"),
+            (None, Column::Index(_)) => unreachable!("numbered columns always resolve"),
+        }
+    }
+
+    /// How to search binary files, going by -a as well as --binary-files.
+    fn binary_files(&self) -> BinaryFiles {
+        if self.text {
+            BinaryFiles::Text
+        } else {
+            self.binary_files
+        }
+    }
+
+    /// Which files a walk of the paths to search should visit.
+    fn walk_options(&self) -> Result<WalkOptions> {
+        let filter = FilterOptions {
+            globs: self.globs.clone(),
+            types: self.types.clone(),
+            types_not: self.types_not.clone(),
+            type_definitions: self.type_definitions.clone(),
+        };
+        Ok(WalkOptions {
+            hidden: self.hidden,
+            no_ignore: self.no_ignore,
+            filter: PathFilter::new(&env::current_dir()?, &filter)?,
+        })
+    }
+
+    /// Layers decompression and decoding over a raw input, as the flags ask for.
+    fn wrap_input(&self, input: Box<dyn Source>) -> Box<dyn Source> {
+        let input: Box<dyn Source> = if self.search_zip {
+            Box::new(DecompressingInput::new(input))
+        } else {
+            input
+        };
+        Box::new(DecodingInput::new(input, self.encoding))
+    }
+}
+
+/// Builds a matcher for `regexes` with the default engine. When it rejects one that -P would
+/// take, like one with look-around or a backreference, says so.
+fn regex_matcher(regexes: &[String], ignore_case: bool) -> Result<RegexMatcher> {
+    RegexMatcher::any_of(regexes, ignore_case).map_err(|error| {
+        if PcreMatcher::any_of(regexes, ignore_case).is_ok() {
+            error.context(
+                "the default regex engine doesn't support look-around or backreferences; \
+                 search with -P to use one that does",
+            )
+        } else {
+            error
+        }
+    })
+}
+
+/// Exits like grep: 0 if anything was found, 1 if nothing was, and 2 if something went wrong,
+/// even if something was found too.
+fn main() -> ExitCode {
+    match run() {
+        Ok(status) => status,
+        Err(error) => {
+            eprintln!("greprs: {error:#}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run() -> Result<ExitCode> {
+    let cli: Vec<OsString> = env::args_os().collect();
+    if let Some(Command::Index(IndexCommand::Build { dir })) = Command::parse_from_cli(&cli) {
+        build_index(&dir)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let mut args = Args::parse_with_config(&cli)?;
+    let patterns = args.take_patterns()?;
+    let matcher = args.build_matcher(&patterns)?;
+    let lists_files = args.files_with_matches || args.files_without_match;
+    // Only whole matching lines are printed with context, so don't go looking for it otherwise
+    let prints_context = !(args.count || lists_files || args.only_matching || args.tui);
+    let context = |lines: Option<usize>| match lines.or(args.context) {
+        Some(lines) if prints_context => lines,
+        _ => 0,
+    };
+    let searcher = Searcher {
+        invert_match: args.invert_match,
+        multiline: args.multiline,
+        // -A and -B take precedence over -C, whichever order they're given in
+        before_context: context(args.before_context),
+        after_context: context(args.after_context),
+        // Whether a file is listed is settled by its first match
+        max_count: if lists_files {
+            Some(args.max_count.map_or(1, |max| max.min(1)))
+        } else {
+            args.max_count
+        },
+        replace: args.replace.as_deref().map(Template::parse),
+        binary_files: args.binary_files(),
+        // A column found by its name is in a header, which isn't a record to search
+        skip_header: matches!(args.csv_column, Some(Column::Name(_))),
+    };
+
+    if args.tui {
+        if args.files.is_empty() {
+            bail!("--tui needs files or directories to search, not stdin");
+        }
+        let needle = patterns.into_iter().next().unwrap_or_default();
+        tui::run(&args, &searcher, needle)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Like grep -r, only prefix results with their path when there's more than one file
+    let with_filename = args.files.len() > 1 || walk::has_directory(&args.files);
+    let options = PrintOptions {
+        with_filename,
+        only_matching: args.only_matching,
+        line_number: args.line_number || args.column,
+        column: args.column,
+        byte_offset: args.byte_offset,
+    };
+    // Matches from a followed file should show up as soon as they're found, not once a buffer
+    // fills up
+    let stdout: Box<dyn Write> = if args.follow {
+        Box::new(LineWriter::new(io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(io::stdout().lock()))
+    };
+    let colored = args.color.enabled(io::stdout().is_terminal());
+    let output: Box<dyn Sink> = match args.color {
+        _ if args.json => Box::new(JsonOutput::new(stdout)),
+        _ if args.count => Box::new(CountOutput::new(stdout, options)),
+        _ if lists_files => Box::new(FileListOutput::new(stdout, args.files_without_match)),
+        _ if !colored => Box::new(PlainOutput::new(stdout, options)),
+        // A color name only ever colored matches, and still does
+        ColorChoice::Matches(color) => Box::new(ColoredOutput::new(
+            stdout,
+            options,
+            Theme::matches_only(color),
+        )),
+        _ => {
+            let mut theme = Theme::from_env();
+            if let Some(spec) = &args.colors {
+                theme.apply(spec);
+            }
+            Box::new(ColoredOutput::new(stdout, options, theme))
+        }
+    };
+    let mut output = TallyingOutput::new(output);
+
+    // Only searches of many files keep going after an error, so only they can have any
+    let errors = if args.follow {
+        let [path] = args.files.as_slice() else {
+            bail!("--follow needs exactly one file to follow");
+        };
+        if path.is_dir() {
+            bail!(
+                "--follow needs a file, not a directory like {}",
+                path.display()
+            );
+        }
+        // Followed until the process is interrupted
+        let stop = Arc::new(AtomicBool::new(false));
+        let input = args.wrap_input(Box::new(FollowInput::new(path, stop)));
+        search_stream(&args, &searcher, &*matcher, &input, &mut output)?;
+        0
+    } else if args.files.is_empty() {
+        if args.in_place.is_some() {
+            bail!("--in-place needs files to rewrite, not stdin");
+        }
+        if args.indexed {
+            bail!("--indexed needs files or directories to search, not stdin");
+        }
+        let input = args.wrap_input(Box::new(StdinInput));
+        search_stream(&args, &searcher, &*matcher, &input, &mut output)?;
+        0
+    } else {
+        let candidates = if args.indexed {
+            let found = Index::find(&fs::canonicalize(&args.files[0])?);
+            let path = found.context(
+                "no index in or above the first path to search; run `greprs index build` first",
+            )?;
+            Some((Index::load(&path)?, args.index_query(&patterns)))
+        } else {
+            None
+        };
+        search_files(
+            &args,
+            &searcher,
+            &*matcher,
+            candidates.as_ref(),
+            &mut output,
+        )?
+    };
+    output.finish()?;
+
+    let given_up = matcher.lines_given_up();
+    if given_up > 0 {
+        let lines = if given_up == 1 { "line" } else { "lines" };
+        eprintln!(
+            "greprs: {given_up} {lines} took more than {} backtracking steps to search, so \
+             counted as not matching",
+            matcher::STEP_LIMIT
+        );
+    }
+
+    let totals = output.totals();
+    // -L finds the files without a match
+    let found = if args.files_without_match {
+        totals.searches > totals.searches_with_match
+    } else {
+        totals.searches_with_match > 0
+    };
+    Ok(match errors + given_up {
+        0 if found => ExitCode::SUCCESS,
+        0 => ExitCode::from(1),
+        _ => ExitCode::from(2),
+    })
+}
+
+/// Indexes the files under `dir`, or updates its index.
+fn build_index(dir: &Path) -> Result<()> {
+    let mut index = Index::open(dir)?;
+    let update = index.update()?;
+    index.save()?;
+    println!(
+        "indexed {} files: {} read, {} removed",
+        update.files, update.read, update.removed
+    );
+    Ok(())
+}
+
+/// Searches the files under the paths given, leaving out any that `candidates`, an index and
+/// what to look up in it, rules out.
+fn search_files(
+    args: &Args,
+    searcher: &Searcher,
+    matcher: &dyn Matcher,
+    candidates: Option<&(Index, Query)>,
+    output: &mut dyn Sink,
+) -> Result<usize> {
+    let files =
+        walk::walk(&args.files, args.walk_options()?).filter(|file| match (file, candidates) {
+            (Ok(file), Some((index, query))) => index.may_match(&file.path, query),
+            _ => true,
+        });
+    let threads = args
+        .threads
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    if threads > 1 {
+        return parallel::search_in_order(files, threads, output, |file, output| {
+            search_file(args, searcher, matcher, file, output)
+        });
+    }
+
+    let mut errors = 0;
+    for file in files {
+        let result = file.and_then(|file| search_file(args, searcher, matcher, &file, output));
+        // One unreadable file shouldn't stop the rest of the search
+        if let Err(error) = result {
+            eprintln!("greprs: {error:#}");
+            errors += 1;
+        }
+    }
+    Ok(errors)
+}
+
+fn search_file(
+    args: &Args,
+    searcher: &Searcher,
+    matcher: &dyn Matcher,
+    file: &WalkedFile,
+    output: &mut dyn Sink,
+) -> Result<()> {
+    let input = args.wrap_input(Box::new(FileInput::new(&file.path)));
+    let as_text = searcher.binary_files == BinaryFiles::Text;
+    let skip_binary = !file.explicit && !args.binary && !as_text;
+
+    if let (Some(suffix), Some(template)) = (&args.in_place, &searcher.replace) {
+        // Rewriting a binary file line by line would corrupt it, unless asked to treat it as text
+        if as_text || !input::looks_binary(input.open()?.fill_buf()?) {
+            let backup_suffix = Some(suffix.as_str()).filter(|suffix| !suffix.is_empty());
+            let changed = replace::rewrite_file(&file.path, matcher, template, backup_suffix)?;
+            // Nothing's printed, but the rewritten lines still count as found
+            let stats = Stats {
+                searches: 1,
+                searches_with_match: u64::from(changed > 0),
+                matched_lines: changed,
+                ..Default::default()
+            };
+            output.begin(input.path())?;
+            output.end(input.path(), &stats)?;
+        }
+        return Ok(());
+    }
+
+    let map = if args.no_mmap { None } else { input.map()? };
+    if let Some(map) = map {
+        if !(skip_binary && input::looks_binary(&map)) {
+            let field = args.field(&map).context(file.path.display().to_string())?;
+            with_field(matcher, field, |matcher| {
+                searcher.search_slice(matcher, &map, input.path(), output)
+            })?;
+        }
+        return Ok(());
+    }
+
+    let mut reader = input.open()?;
+    if !(skip_binary && input::looks_binary(reader.fill_buf()?)) {
+        let (field, mut reader) =
+            stream_field(args, reader).context(file.path.display().to_string())?;
+        with_field(matcher, field, |matcher| {
+            searcher.search_reader(matcher, &mut *reader, input.path(), output)
+        })?;
+    }
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    println!("{:?}", args);
+/// Searches an input that can only be read as it comes, like stdin or a followed file.
+fn search_stream(
+    args: &Args,
+    searcher: &Searcher,
+    matcher: &dyn Matcher,
+    input: &dyn Source,
+    output: &mut dyn Sink,
+) -> Result<()> {
+    let (field, mut reader) = stream_field(args, input.open()?)?;
+    with_field(matcher, field, |matcher| {
+        searcher.search_reader(matcher, &mut *reader, input.path(), output)
+    })?;
     Ok(())
 }
+
+/// Works out the field to search in an input that's read as it comes. A CSV header is read as a
+/// whole line, since what a reader has buffered to begin with can stop short of its end, and
+/// then put back in front of the rest of the input.
+fn stream_field(
+    args: &Args,
+    mut reader: Box<dyn BufRead>,
+) -> Result<(Option<Field>, Box<dyn BufRead>)> {
+    if args.csv_column.is_none() {
+        return Ok((args.field(&[])?, reader));
+    }
+    let mut header = Vec::new();
+    reader.read_until(b'\n', &mut header)?;
+    let field = args.field(&header)?;
+    Ok((field, Box::new(io::Cursor::new(header).chain(reader))))
+}
+
+/// Runs `search` with `matcher`, narrowed down to `field` if there is one.
+fn with_field<T>(
+    matcher: &dyn Matcher,
+    field: Option<Field>,
+    search: impl FnOnce(&dyn Matcher) -> T,
+) -> T {
+    match field {
+        Some(field) => search(&FieldMatcher::new(matcher, field)),
+        None => search(matcher),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("greprs").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn test_take_patterns_from_needle() {
+        let mut args = parse(&["needle", "a.txt"]);
+        assert_eq!(args.take_patterns().unwrap(), vec!["needle"]);
+        assert_eq!(args.files, vec![PathBuf::from("a.txt")]);
+    }
+
+    #[test]
+    fn test_take_patterns_from_flags() {
+        let mut patterns = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut patterns, b"third\r\nfourth\n").unwrap();
+        let pattern_file = patterns.path().to_str().unwrap();
+
+        let mut args = parse(&["-e", "first", "a.txt", "-e", "second", "-f", pattern_file]);
+        assert_eq!(
+            args.take_patterns().unwrap(),
+            vec!["first", "second", "third", "fourth"]
+        );
+        // The positional argument is a file once patterns come from flags
+        assert_eq!(args.files, vec![PathBuf::from("a.txt")]);
+    }
+
+    #[test]
+    fn test_needle_is_required_without_pattern_flags() {
+        assert!(Args::try_parse_from(["greprs"]).is_err());
+        assert!(Args::try_parse_from(["greprs", "-e", "needle"]).is_ok());
+    }
+
+    #[test]
+    fn test_in_place_suffix() {
+        assert_eq!(
+            parse(&["--replace", "x", "--in-place", "a"])
+                .in_place
+                .as_deref(),
+            Some("")
+        );
+        let args = parse(&["--replace", "x", "--in-place=.bak", "a", "b.txt"]);
+        assert_eq!(args.in_place.as_deref(), Some(".bak"));
+        assert_eq!(args.files, vec![PathBuf::from("b.txt")]);
+        // Rewriting only makes sense with something to rewrite to
+        assert!(Args::try_parse_from(["greprs", "--in-place", "a", "b.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_count_and_color_flags() {
+        let args = parse(&["--count", "needle"]);
+        assert!(args.count);
+        assert_eq!(args.color, ColorChoice::Auto);
+        assert_eq!(
+            parse(&["--color", "never", "needle"]).color,
+            ColorChoice::Never
+        );
+        assert_eq!(
+            parse(&["--color=always", "needle"]).color,
+            ColorChoice::Always
+        );
+        // A bare color name still works, and means always
+        assert_eq!(
+            parse(&["--color", "red", "needle"]).color,
+            ColorChoice::Matches(colored::Color::Red)
+        );
+        assert!(Args::try_parse_from(["greprs", "--color", "sometimes", "needle"]).is_err());
+        assert!(Args::try_parse_from(["greprs", "--count", "-l", "needle"]).is_err());
+        // -c is short for --color, as it always has been
+        let args = parse(&["-c", "red", "apple", "c.txt"]);
+        assert!(!args.count);
+        assert_eq!(args.color, ColorChoice::Matches(colored::Color::Red));
+        assert_eq!(args.files, vec![PathBuf::from("c.txt")]);
+    }
+
+    #[test]
+    fn test_binary_files_flags() {
+        assert_eq!(parse(&["needle"]).binary_files(), BinaryFiles::Binary);
+        assert_eq!(
+            parse(&["--binary-files=without-match", "needle"]).binary_files(),
+            BinaryFiles::WithoutMatch
+        );
+        assert_eq!(parse(&["-a", "needle"]).binary_files(), BinaryFiles::Text);
+        assert!(Args::try_parse_from(["greprs", "--binary-files", "hex", "needle"]).is_err());
+        assert!(Args::try_parse_from(["greprs", "-a", "--binary-files=text", "needle"]).is_err());
+    }
+
+    #[test]
+    fn test_follow_flag() {
+        assert!(parse(&["--follow", "error", "app.log"]).follow);
+        assert!(!parse(&["error", "app.log"]).follow);
+        // Counts and file lists are only known once a file ends, which a followed one never does
+        assert!(
+            Args::try_parse_from(["greprs", "--follow", "--count", "error", "app.log"]).is_err()
+        );
+        assert!(Args::try_parse_from(["greprs", "--follow", "-U", "error", "app.log"]).is_err());
+    }
+
+    #[test]
+    fn test_field_flags() {
+        let args = parse(&["--jsonl-field", "req.path", "needle"]);
+        assert_eq!(args.field(b"{}").unwrap(), Some(Field::json("req.path")));
+        assert_eq!(parse(&["needle"]).field(b"a,b").unwrap(), None);
+
+        let args = parse(&["--csv-column", "2", "needle"]);
+        assert_eq!(args.field(b"a,b\n").unwrap(), Some(Field::Csv(1)));
+        let args = parse(&["--csv-column", "email", "needle"]);
+        assert_eq!(
+            args.field(b"id,email\n1,a@b.c\n").unwrap(),
+            Some(Field::Csv(1))
+        );
+        assert!(args.field(b"id,name\n").is_err());
+        assert!(Args::try_parse_from(["greprs", "--csv-column", "0", "needle"]).is_err());
+        assert!(
+            Args::try_parse_from(["greprs", "--csv-column=1", "--jsonl-field=a", "needle"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_index_subcommand() {
+        let command = |args: &[&str]| {
+            let cli: Vec<OsString> = std::iter::once("greprs")
+                .chain(args.iter().copied())
+                .map(OsString::from)
+                .collect();
+            Command::parse_from_cli(&cli)
+        };
+        assert!(matches!(
+            command(&["index", "build", "src"]),
+            Some(Command::Index(IndexCommand::Build { dir })) if dir == Path::new("src")
+        ));
+        assert!(matches!(
+            command(&["index", "build"]),
+            Some(Command::Index(IndexCommand::Build { dir })) if dir == Path::new(".")
+        ));
+        // Without one of its subcommands after it, "index" is just a needle to search for
+        for args in [
+            &["index", "a.txt"][..],
+            &["index"],
+            &["-i", "index", "build"],
+        ] {
+            assert!(command(args).is_none(), "{args:?}");
+        }
+        let args = parse(&["index", "a.txt"]);
+        assert_eq!(args.needle.as_deref(), Some("index"));
+        assert_eq!(args.files, vec![PathBuf::from("a.txt")]);
+        let args = parse(&["-i", "index", "build"]);
+        assert_eq!(args.needle.as_deref(), Some("index"));
+        assert_eq!(args.files, vec![PathBuf::from("build")]);
+        assert!(command(&["indexed", "a.txt"]).is_none());
+    }
+
+    #[test]
+    fn test_index_query() {
+        let query = |args: &[&str]| {
+            let mut args = parse(args);
+            let patterns = args.take_patterns().unwrap();
+            args.index_query(&patterns)
+        };
+        let literal = |bytes: &str| Query::Literal(bytes.as_bytes().to_vec());
+        // Literal needles are escaped, so their dots aren't wildcards
+        assert_eq!(
+            query(&["-e", "a.out", "-e", "x"]),
+            Query::Any(vec![literal("a.out"), literal("x")])
+        );
+        assert_eq!(
+            query(&["-w", "Main"]),
+            Query::Any(vec![Query::All(vec![literal("main")])])
+        );
+        assert_eq!(query(&["-v", "Main"]), Query::Everything);
+        assert_eq!(query(&["--fuzzy", "1", "Main"]), Query::Everything);
+        assert_eq!(query(&["-P", "Main"]), Query::Everything);
+    }
+
+    #[test]
+    fn test_type_and_glob_flags() {
+        let args = parse(&["-t", "rust", "-T", "md", "-g", "!target/", "needle", "."]);
+        assert_eq!(args.types, vec!["rust"]);
+        assert_eq!(args.types_not, vec!["md"]);
+        assert_eq!(args.globs, vec!["!target/"]);
+        assert_eq!(args.files, vec![PathBuf::from(".")]);
+    }
+
+    fn matcher(args: &[&str]) -> Box<dyn Matcher> {
+        let mut args = parse(args);
+        let patterns = args.take_patterns().unwrap();
+        args.build_matcher(&patterns).unwrap()
+    }
+
+    #[test]
+    fn test_smart_case() {
+        let lower = matcher(&["-S", "error"]);
+        assert!(lower.is_match(b"ERROR: disk full"));
+        let upper = matcher(&["-S", "Error"]);
+        assert!(!upper.is_match(b"ERROR: disk full"));
+        assert!(upper.is_match(b"Error: disk full"));
+        // Escapes in a regex aren't uppercase letters
+        assert!(matcher(&["-S", "-r", r"\Derror"]).is_match(b"1 ERROR"));
+    }
+
+    #[test]
+    fn test_word_and_line_regexp() {
+        let words = matcher(&["-w", "a.b"]);
+        assert!(words.is_match(b"see a.b here"));
+        assert!(!words.is_match(b"see axb here"));
+        assert!(!words.is_match(b"a.bc"));
+        assert!(matcher(&["-w", "-r", "a.b"]).is_match(b"axb"));
+
+        let lines = matcher(&["-x", "-i", "-e", "foo", "-e", "bar"]);
+        assert!(lines.is_match(b"BAR"));
+        assert!(!lines.is_match(b"foobar"));
+
+        let all_of = matcher(&["-w", "--all-of", "-e", "cat", "-e", "dog"]);
+        assert!(all_of.is_match(b"dog and cat"));
+        assert!(!all_of.is_match(b"dogs and cats"));
+    }
+
+    #[test]
+    fn test_pcre() {
+        let pcre = matcher(&["-P", "-w", r"(\w+) \1"]);
+        assert!(pcre.is_match(b"it is is"));
+        assert!(!pcre.is_match(b"it is isn't"));
+        // Patterns are regexes, so smart case skips their escapes
+        assert!(matcher(&["-P", "-S", r"\Derror(?!s)"]).is_match(b"1 ERROR"));
+        assert!(Args::try_parse_from(["greprs", "-P", "--fuzzy", "1", "needle"]).is_err());
+
+        // The default engine suggests -P for what only it can do, but not for mistakes
+        let mut args = parse(&["-r", "foo(?=bar)"]);
+        let patterns = args.take_patterns().unwrap();
+        let error = args.build_matcher(&patterns).err().unwrap();
+        assert!(format!("{error:#}").contains("-P"));
+        let mut args = parse(&["-r", "(unclosed"]);
+        let patterns = args.take_patterns().unwrap();
+        let error = args.build_matcher(&patterns).err().unwrap();
+        assert!(!format!("{error:#}").contains("-P"));
+    }
+
+    #[test]
+    fn test_build_matcher_all_of() {
+        let mut args = parse(&["--all-of", "-e", "apple", "-e", "pie"]);
+        let patterns = args.take_patterns().unwrap();
+        let matcher = args.build_matcher(&patterns).unwrap();
+        assert!(matcher.is_match(b"apple pie"));
+        assert!(!matcher.is_match(b"apple crumble"));
+    }
+}
@@ -0,0 +1,580 @@
+mod field;
+mod fuzzy;
+mod pcre;
+
+pub use field::{Column, Field, FieldMatcher};
+pub use fuzzy::FuzzyMatcher;
+pub use pcre::{PcreMatcher, STEP_LIMIT};
+
+use crate::replace::{Group, Template};
+use aho_corasick::{AhoCorasick, MatchKind};
+use anyhow::Result;
+use memchr::memmem::Finder;
+use regex::bytes::{Regex, RegexBuilder};
+use std::ops::Range;
+
+/// Something that can find a needle inside a haystack of bytes. Matchers are shared between the
+/// threads searching different files, so they need to be thread safe.
+pub trait Matcher: Send + Sync {
+    /// Returns the byte range of the first match starting at or after `at`.
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>>;
+
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find_at(haystack, 0).is_some()
+    }
+
+    /// Returns every non-overlapping match in `haystack`, left to right.
+    fn find_all(&self, haystack: &[u8]) -> Vec<Range<usize>> {
+        let mut matches = Vec::new();
+        let mut at = 0;
+        while at <= haystack.len() {
+            let Some(found) = self.find_at(haystack, at) else {
+                break;
+            };
+            // An empty match would otherwise be found again at the same spot
+            at = if found.is_empty() {
+                found.end + 1
+            } else {
+                found.end
+            };
+            matches.push(found);
+        }
+        matches
+    }
+
+    /// Appends `template` to `dst` with its references filled in from the match at `found`, for
+    /// `--replace`. Matchers without capture groups only know about `$0`, the whole match.
+    fn expand(&self, haystack: &[u8], found: Range<usize>, template: &Template, dst: &mut Vec<u8>) {
+        template.expand(dst, |group| {
+            (*group == Group::Index(0)).then(|| &haystack[found.clone()])
+        });
+    }
+
+    /// How many lines the matcher has given up on so far because searching them took too long,
+    /// which it treated as not matching. Only backtracking matchers ever give up.
+    fn lines_given_up(&self) -> usize {
+        0
+    }
+
+    /// Whether searches should hand the matcher one line at a time, instead of a whole buffer to
+    /// look for matches in. Worth it for a matcher that does work in proportion to the rest of the
+    /// buffer every time it's asked for the next match, like a backtracking one.
+    fn searches_lines(&self) -> bool {
+        false
+    }
+}
+
+// Lets a matcher wrap one it only borrows, like the one every thread shares
+impl<M: Matcher + ?Sized> Matcher for &M {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        (**self).find_at(haystack, at)
+    }
+
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        (**self).is_match(haystack)
+    }
+
+    fn find_all(&self, haystack: &[u8]) -> Vec<Range<usize>> {
+        (**self).find_all(haystack)
+    }
+
+    fn expand(&self, haystack: &[u8], found: Range<usize>, template: &Template, dst: &mut Vec<u8>) {
+        (**self).expand(haystack, found, template, dst)
+    }
+
+    fn lines_given_up(&self) -> usize {
+        (**self).lines_given_up()
+    }
+    fn searches_lines(&self) -> bool {
+        (**self).searches_lines()
+    }
+}
+
+/// What a match has to line up with, as with `-w` and `-x`. Boundaries are enforced by wrapping
+/// a regex, so literal needles need escaping into regexes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Boundary {
+    #[default]
+    None,
+    /// There can't be a word character right before or after a match, so a search for "cat"
+    /// finds "cat!" but not "concatenate". Word characters are Unicode letters, digits and
+    /// connecting punctuation like `_`.
+    Word,
+    /// A match has to cover its whole line.
+    Line,
+}
+
+impl Boundary {
+    /// Wraps `pattern` so it only matches at this boundary.
+    pub fn wrap(self, pattern: &str) -> String {
+        match self {
+            Boundary::None => pattern.to_string(),
+            // Half boundaries only look outwards, so patterns can still start or end with
+            // something other than a word character, like `-w '@types'`
+            Boundary::Word => format!(r"\b{{start-half}}(?:{pattern})\b{{end-half}}"),
+            // RegexMatcher turns on multi_line, so these anchor to the ends of the line
+            Boundary::Line => format!("^(?:{pattern})$"),
+        }
+    }
+}
+
+/// Whether `pattern` asks for an uppercase letter, which turns `--smart-case` off. In a regex,
+/// escapes like `\S` and `\p{Lu}` don't count, since they aren't letters to match.
+pub fn has_uppercase(pattern: &str, is_regex: bool) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if is_regex && c == '\\' {
+            if let Some('p' | 'P') = chars.next() {
+                // Skip a braced class name, like the Lu in \p{Lu}
+                if chars.clone().next() == Some('{') {
+                    chars.by_ref().find(|&c| c == '}');
+                }
+            }
+            continue;
+        }
+        if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Matches plain strings, byte for byte.
+pub struct LiteralMatcher {
+    search: LiteralSearch,
+}
+
+enum LiteralSearch {
+    Single(Box<Finder<'static>>),
+    // An Aho-Corasick automaton finds any of hundreds of needles in a single pass
+    Many(AhoCorasick),
+    // Unicode case folding can change byte lengths, so leave it to the regex engine
+    Folded(Regex),
+}
+
+impl LiteralMatcher {
+    pub fn new(needle: &str, ignore_case: bool) -> Result<Self> {
+        Self::any_of(&[needle], ignore_case)
+    }
+
+    /// Matches any one of `needles`, preferring the longest when several start at the same
+    /// place. With no needles at all, nothing matches.
+    pub fn any_of(needles: &[impl AsRef<str>], ignore_case: bool) -> Result<Self> {
+        let needles: Vec<&str> = needles.iter().map(AsRef::as_ref).collect();
+        let search = match needles[..] {
+            [needle] if !ignore_case => {
+                LiteralSearch::Single(Box::new(Finder::new(needle.as_bytes()).into_owned()))
+            }
+            _ if ignore_case && !needles.iter().all(|needle| needle.is_ascii()) => {
+                let mut needles = needles;
+                // The regex engine prefers earlier alternatives, so put longer needles first
+                needles.sort_by_key(|needle| std::cmp::Reverse(needle.len()));
+                let alternation: Vec<_> =
+                    needles.iter().map(|needle| regex::escape(needle)).collect();
+                LiteralSearch::Folded(
+                    RegexBuilder::new(&alternation.join("|"))
+                        .case_insensitive(true)
+                        .build()?,
+                )
+            }
+            _ => LiteralSearch::Many(
+                AhoCorasick::builder()
+                    .match_kind(MatchKind::LeftmostLongest)
+                    .ascii_case_insensitive(ignore_case)
+                    .build(&needles)?,
+            ),
+        };
+        Ok(Self { search })
+    }
+}
+
+impl Matcher for LiteralMatcher {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        if at > haystack.len() {
+            return None;
+        }
+        match &self.search {
+            LiteralSearch::Single(finder) => {
+                let start = at + finder.find(&haystack[at..])?;
+                Some(start..start + finder.needle().len())
+            }
+            LiteralSearch::Many(automaton) => {
+                let input = aho_corasick::Input::new(haystack).range(at..);
+                automaton.find(input).map(|m| m.range())
+            }
+            LiteralSearch::Folded(regex) => regex.find_at(haystack, at).map(|m| m.range()),
+        }
+    }
+}
+
+/// Matches a regular expression using the `regex` crate.
+pub struct RegexMatcher {
+    regex: Regex,
+    /// With several patterns, each is wrapped in a group of its own. This holds the index of
+    /// that group for each pattern, and how many groups the pattern has inside it.
+    patterns: Vec<(usize, usize)>,
+}
+
+impl RegexMatcher {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self> {
+        Self::any_of(&[pattern], ignore_case)
+    }
+
+    /// Matches any one of `patterns`. With no patterns at all, nothing matches.
+    pub fn any_of(patterns: &[impl AsRef<str>], ignore_case: bool) -> Result<Self> {
+        // Groups are numbered across the whole alternation, so with several patterns, wrap each
+        // in a group that tells which one matched, to number groups within it instead
+        let wrap = if patterns.len() > 1 { "(" } else { "(?:" };
+        let alternation = if patterns.is_empty() {
+            // An empty class, which can never match
+            r"[^\s\S]".to_string()
+        } else {
+            let groups: Vec<_> = patterns
+                .iter()
+                .map(|pattern| format!("{wrap}{})", pattern.as_ref()))
+                .collect();
+            groups.join("|")
+        };
+        // Matchers may be run over many lines at once, so make ^ and $ mean the start and end of
+        // a line rather than of the whole haystack
+        let regex = RegexBuilder::new(&alternation)
+            .case_insensitive(ignore_case)
+            .multi_line(true)
+            .crlf(true)
+            .build()?;
+        let mut wrapped = Vec::new();
+        if patterns.len() > 1 {
+            let mut index = 1;
+            for pattern in patterns {
+                let hir = regex_syntax::Parser::new().parse(pattern.as_ref())?;
+                let groups = hir.properties().explicit_captures_len();
+                wrapped.push((index, groups));
+                index += groups + 1;
+            }
+        }
+        Ok(Self {
+            regex,
+            patterns: wrapped,
+        })
+    }
+}
+
+impl Matcher for RegexMatcher {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        if at > haystack.len() {
+            return None;
+        }
+        self.regex.find_at(haystack, at).map(|m| m.range())
+    }
+
+    fn expand(&self, haystack: &[u8], found: Range<usize>, template: &Template, dst: &mut Vec<u8>) {
+        // Searching again from the start of the match finds the same match, with its groups
+        let Some(captures) = self.regex.captures_at(haystack, found.start) else {
+            return;
+        };
+        // Number groups within the pattern that matched, as if it had been searched for alone
+        let matched = self
+            .patterns
+            .iter()
+            .find(|(wrapper, _)| captures.get(*wrapper).is_some());
+        template.expand(dst, |group| {
+            match (group, matched) {
+                (Group::Index(0), _) => captures.get(0),
+                (Group::Index(index), Some((wrapper, groups))) => {
+                    (index <= groups).then(|| captures.get(wrapper + index))?
+                }
+                (Group::Index(index), None) => captures.get(*index),
+                (Group::Name(name), _) => captures.name(name),
+            }
+            .map(|m| m.as_bytes())
+        });
+    }
+}
+
+/// Only matches lines where every one of its matchers finds something, as with `--all-of`.
+///
+/// `find_at` reports where any of the matchers finds something, so it can be used to look for
+/// candidate lines in a larger buffer, while `is_match` and `find_all` only succeed when all of
+/// the matchers do.
+pub struct AllOfMatcher {
+    matchers: Vec<Box<dyn Matcher>>,
+}
+
+impl AllOfMatcher {
+    pub fn new(matchers: Vec<Box<dyn Matcher>>) -> Self {
+        Self { matchers }
+    }
+}
+
+impl Matcher for AllOfMatcher {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        self.matchers
+            .iter()
+            .filter_map(|matcher| matcher.find_at(haystack, at))
+            .min_by_key(|found| (found.start, std::cmp::Reverse(found.end)))
+    }
+
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        !self.matchers.is_empty()
+            && self
+                .matchers
+                .iter()
+                .all(|matcher| matcher.is_match(haystack))
+    }
+
+    fn find_all(&self, haystack: &[u8]) -> Vec<Range<usize>> {
+        let mut all = Vec::new();
+        for matcher in &self.matchers {
+            let matches = matcher.find_all(haystack);
+            if matches.is_empty() {
+                return Vec::new();
+            }
+            all.extend(matches);
+        }
+        // Keep the result in order and non-overlapping, like any other matcher's
+        all.sort_by_key(|found| (found.start, std::cmp::Reverse(found.end)));
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(all.len());
+        for found in all {
+            match merged.last() {
+                Some(last) if found.start < last.end || found == *last => {}
+                _ => merged.push(found),
+            }
+        }
+        merged
+    }
+
+    fn expand(&self, haystack: &[u8], found: Range<usize>, template: &Template, dst: &mut Vec<u8>) {
+        // Only the matcher that found the match knows about its groups
+        let matcher = self
+            .matchers
+            .iter()
+            .find(|matcher| matcher.find_at(haystack, found.start) == Some(found.clone()));
+        match matcher {
+            Some(matcher) => matcher.expand(haystack, found, template, dst),
+            None => template.expand(dst, |group| {
+                (*group == Group::Index(0)).then(|| &haystack[found.clone()])
+            }),
+        }
+    }
+
+    fn lines_given_up(&self) -> usize {
+        self.matchers
+            .iter()
+            .map(|matcher| matcher.lines_given_up())
+            .sum()
+    }
+    fn searches_lines(&self) -> bool {
+        self.matchers.iter().any(|matcher| matcher.searches_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_matcher() {
+        let matcher = LiteralMatcher::new("fox", false).unwrap();
+        assert!(matcher.is_match(b"the quick brown fox"));
+        assert!(!matcher.is_match(b"the quick brown FOX"));
+        assert_eq!(matcher.find_at(b"fox fox", 1), Some(4..7));
+        assert_eq!(matcher.find_all(b"fox and fox"), vec![0..3, 8..11]);
+
+        // Regex metacharacters are not special in a literal
+        let matcher = LiteralMatcher::new("a.c", false).unwrap();
+        assert!(matcher.is_match(b"xa.cx"));
+        assert!(!matcher.is_match(b"abc"));
+    }
+
+    #[test]
+    fn test_literal_matcher_ignore_case() {
+        let matcher = LiteralMatcher::new("fox", true).unwrap();
+        assert_eq!(matcher.find_all(b"Fox and FOX"), vec![0..3, 8..11]);
+
+        let matcher = LiteralMatcher::new("straße", true).unwrap();
+        assert!(matcher.is_match("STRAßE".as_bytes()));
+    }
+
+    #[test]
+    fn test_regex_matcher() {
+        let matcher = RegexMatcher::new(r"\d+", false).unwrap();
+        assert_eq!(matcher.find_all(b"a1 b22 c333"), vec![1..2, 4..6, 8..11]);
+        assert!(!matcher.is_match(b"no digits"));
+
+        let matcher = RegexMatcher::new("^error", true).unwrap();
+        assert!(matcher.is_match(b"ERROR: disk full"));
+        assert!(!matcher.is_match(b"no error here"));
+
+        assert!(RegexMatcher::new("(unclosed", false).is_err());
+    }
+
+    #[test]
+    fn test_regex_matcher_any_of_numbers_groups_per_pattern() {
+        let matcher =
+            RegexMatcher::any_of(&[r"zz(\d)", r"(\w+)=(?<value>\w+)", "x"], false).unwrap();
+        let expand = |line: &[u8], template: &str| {
+            let mut dst = Vec::new();
+            let template = Template::parse(template);
+            crate::replace::replace_matches(
+                &matcher,
+                line,
+                &matcher.find_all(line),
+                &template,
+                &mut dst,
+            );
+            String::from_utf8(dst).unwrap()
+        };
+        assert_eq!(expand(b"val=1 here", "[$1]"), "[val] here");
+        assert_eq!(expand(b"zz7 key=v", "[$1|$2|${value}]"), "[7||] [key|v|v]");
+        assert_eq!(expand(b"x", "[$0$1]"), "[x]");
+    }
+
+    #[test]
+    fn test_regex_matcher_anchors_match_lines() {
+        let matcher = RegexMatcher::new("^b$", false).unwrap();
+        assert_eq!(matcher.find_all(b"a\nb\r\nc"), vec![2..3]);
+    }
+
+    #[test]
+    fn test_find_all_empty_matches() {
+        let matcher = RegexMatcher::new("x*", false).unwrap();
+        assert_eq!(matcher.find_all(b"ab"), vec![0..0, 1..1, 2..2]);
+        assert!(matcher.is_match(b""));
+    }
+
+    #[test]
+    fn test_literal_matcher_any_of() {
+        let matcher = LiteralMatcher::any_of(&["cat", "dog", "category"], false).unwrap();
+        assert_eq!(
+            matcher.find_all(b"dog category hotdog"),
+            vec![0..3, 4..12, 16..19]
+        );
+        assert!(!matcher.is_match(b"Dog"));
+
+        let matcher = LiteralMatcher::any_of(&["cat", "dog"], true).unwrap();
+        assert_eq!(matcher.find_all(b"DOG Cat"), vec![0..3, 4..7]);
+        assert_eq!(matcher.find_at(b"DOG Cat", 1), Some(4..7));
+        assert_eq!(matcher.find_at(b"DOG Cat", 8), None);
+
+        // Non-ASCII needles still fold case properly
+        let matcher = LiteralMatcher::any_of(&["ÉTÉ", "été d'or"], true).unwrap();
+        assert_eq!(matcher.find_all("un Été D'OR".as_bytes()), vec![3..13]);
+    }
+
+    #[test]
+    fn test_literal_matcher_with_no_needles() {
+        let matcher = LiteralMatcher::any_of(&[] as &[&str], false).unwrap();
+        assert!(!matcher.is_match(b"anything"));
+        assert!(!matcher.is_match(b""));
+    }
+
+    #[test]
+    fn test_regex_matcher_any_of() {
+        let matcher = RegexMatcher::any_of(&[r"\d+", "^[a-z]+"], false).unwrap();
+        assert_eq!(matcher.find_all(b"abc 12 de 3"), vec![0..3, 4..6, 10..11]);
+
+        let matcher = RegexMatcher::any_of(&[] as &[&str], false).unwrap();
+        assert!(!matcher.is_match(b"anything"));
+        assert!(!matcher.is_match(b""));
+    }
+
+    fn bounded(pattern: &str, boundary: Boundary) -> RegexMatcher {
+        RegexMatcher::new(&boundary.wrap(pattern), false).unwrap()
+    }
+
+    #[test]
+    fn test_word_boundary() {
+        let matcher = bounded("cat", Boundary::Word);
+        assert_eq!(
+            matcher.find_all(b"cat concatenate cat_food cat!"),
+            vec![0..3, 25..28]
+        );
+
+        // Letters outside ASCII are word characters too
+        let matcher = bounded("caf", Boundary::Word);
+        assert!(!matcher.is_match("un café".as_bytes()));
+        assert!(matcher.is_match("un caf, été".as_bytes()));
+        let matcher = bounded("été", Boundary::Word);
+        assert!(!matcher.is_match("étés".as_bytes()));
+
+        // Patterns can start with something that isn't a word character
+        let matcher = bounded("@types", Boundary::Word);
+        assert!(matcher.is_match(b"import @types/node"));
+        assert!(!matcher.is_match(b"@typescript"));
+
+        let matcher = bounded("a|ab", Boundary::Word);
+        assert_eq!(matcher.find_all(b"ab"), vec![0..2]);
+    }
+
+    #[test]
+    fn test_line_boundary() {
+        let matcher = bounded(&regex::escape("a.b"), Boundary::Line);
+        assert!(matcher.is_match(b"a.b"));
+        assert!(!matcher.is_match(b"a.b c"));
+        assert!(!matcher.is_match(b"axb"));
+        assert_eq!(matcher.find_all(b"x\na.b\r\na.bc"), vec![2..5]);
+
+        let matcher = bounded("foo|bar", Boundary::Line);
+        assert!(matcher.is_match(b"bar"));
+        assert!(!matcher.is_match(b"foobar"));
+    }
+
+    #[test]
+    fn test_has_uppercase() {
+        assert!(has_uppercase("Error", false));
+        assert!(has_uppercase("ÉTÉ", false));
+        assert!(!has_uppercase("error", false));
+        assert!(has_uppercase(r"\S", false));
+        assert!(!has_uppercase(r"\S+\W", true));
+        assert!(!has_uppercase(r"\p{Lu}x", true));
+        assert!(has_uppercase(r"\p{Lu}X", true));
+        assert!(has_uppercase(r"\d[A-Z]", true));
+    }
+
+    #[test]
+    fn test_all_of_matcher() {
+        let matcher = AllOfMatcher::new(vec![
+            Box::new(LiteralMatcher::new("error", false).unwrap()),
+            Box::new(RegexMatcher::new(r"\d+", false).unwrap()),
+        ]);
+        assert!(matcher.is_match(b"error 42"));
+        assert!(!matcher.is_match(b"error"));
+        assert!(!matcher.is_match(b"42"));
+        assert_eq!(matcher.find_all(b"42 errors, 7"), vec![0..2, 3..8, 11..12]);
+        assert!(matcher
+            .find_all(b"no numbers here, just an error")
+            .is_empty());
+
+        // As a candidate search, it stops at whatever any matcher finds first
+        assert_eq!(matcher.find_at(b"x 1 error", 0), Some(2..3));
+    }
+
+    #[test]
+    fn test_all_of_matcher_expands_with_the_matcher_that_found_it() {
+        let matcher = AllOfMatcher::new(vec![
+            Box::new(RegexMatcher::new(r"(\w+)=", false).unwrap()),
+            Box::new(LiteralMatcher::new("here", false).unwrap()),
+        ]);
+        let line = b"val= here";
+        let mut dst = Vec::new();
+        let template = Template::parse("[$1]");
+        crate::replace::replace_matches(
+            &matcher,
+            line,
+            &matcher.find_all(line),
+            &template,
+            &mut dst,
+        );
+        assert_eq!(dst, b"[val] []");
+    }
+
+    #[test]
+    fn test_all_of_matcher_merges_overlapping_matches() {
+        let matcher = AllOfMatcher::new(vec![
+            Box::new(LiteralMatcher::new("foobar", false).unwrap()),
+            Box::new(LiteralMatcher::new("bar", false).unwrap()),
+            Box::new(LiteralMatcher::new("foo", false).unwrap()),
+        ]);
+        assert_eq!(matcher.find_all(b"foobar bar"), vec![0..6, 7..10]);
+    }
+}
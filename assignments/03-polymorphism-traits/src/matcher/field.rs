@@ -0,0 +1,418 @@
+use super::Matcher;
+use crate::replace::Template;
+use crate::search::trim_line_terminator;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// The part of each record to search, as chosen with `--jsonl-field` or `--csv-column`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// A value in a JSON Lines record, found by following a path of object keys and array
+    /// indices from the top level.
+    Json(Vec<String>),
+    /// A column of a CSV record, counting from 0.
+    Csv(usize),
+}
+
+impl Field {
+    /// A JSON Lines field at a dotted path like `request.headers.0`, where numbers index arrays.
+    pub fn json(path: &str) -> Self {
+        Field::Json(path.split('.').map(str::to_string).collect())
+    }
+
+    /// Finds this field in `record`, a single line without its terminator. For a string the
+    /// range covers what's between its quotes, and anything else is covered whole.
+    pub fn locate(&self, record: &[u8]) -> Option<Range<usize>> {
+        match self {
+            Field::Json(path) => JsonScanner::new(record).field(path),
+            Field::Csv(index) => csv_columns(record).nth(*index),
+        }
+    }
+}
+
+/// A CSV column given by its header name or by its position, counting from 1 like `cut -f`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// Counting from 0, despite being given counting from 1.
+    Index(usize),
+    Name(String),
+}
+
+impl Column {
+    /// Which column this is, counting from 0, going by a CSV file's first line if it's a name.
+    pub fn resolve(&self, header: &[u8]) -> Option<usize> {
+        match self {
+            Column::Index(index) => Some(*index),
+            Column::Name(name) => {
+                let header = trim_line_terminator(header);
+                csv_columns(header).position(|range| unquote_csv(&header[range]) == name.as_bytes())
+            }
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(column: &str) -> Result<Self, Self::Err> {
+        if column.is_empty() {
+            return Err("expected a column name or number".to_string());
+        }
+        if !column.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Ok(Column::Name(column.to_string()));
+        }
+        match column.parse::<usize>() {
+            Ok(number) if number > 0 => Ok(Column::Index(number - 1)),
+            _ => Err(format!("columns are numbered from 1, not {column:?}")),
+        }
+    }
+}
+
+/// Only matches inside one field of each line, treating lines as CSV or JSON Lines records, for
+/// `--csv-column` and `--jsonl-field`. Lines without the field never match.
+///
+/// Matches are reported where they are in the whole line, so the whole record is printed with
+/// just the part of the field that matched highlighted. The field is searched as it's written in
+/// the record, so escapes inside a JSON string or a quoted CSV value aren't decoded first.
+pub struct FieldMatcher<M> {
+    inner: M,
+    field: Field,
+}
+
+impl<M: Matcher> FieldMatcher<M> {
+    pub fn new(inner: M, field: Field) -> Self {
+        Self { inner, field }
+    }
+
+    /// Where the field is in the line of `haystack` that `at` falls on, if it's there at all.
+    fn field_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        let start = memchr::memrchr(b'\n', &haystack[..at]).map_or(0, |newline| newline + 1);
+        let end =
+            memchr::memchr(b'\n', &haystack[at..]).map_or(haystack.len(), |newline| at + newline);
+        let record = trim_line_terminator(&haystack[start..end]);
+        let field = self.field.locate(record)?;
+        Some(start + field.start..start + field.end)
+    }
+}
+
+impl<M: Matcher> Matcher for FieldMatcher<M> {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        if at > haystack.len() {
+            return None;
+        }
+        // The haystack may hold many lines, so look in each one's field in turn
+        let mut line_start = at;
+        loop {
+            let line_end = memchr::memchr(b'\n', &haystack[line_start..])
+                .map_or(haystack.len(), |newline| line_start + newline);
+            if let Some(field) = self.field_at(haystack, line_start) {
+                if at <= field.end {
+                    let from = at.max(field.start) - field.start;
+                    if let Some(found) = self.inner.find_at(&haystack[field.clone()], from) {
+                        return Some(field.start + found.start..field.start + found.end);
+                    }
+                }
+            }
+            if line_end == haystack.len() {
+                return None;
+            }
+            line_start = line_end + 1;
+        }
+    }
+
+    fn expand(&self, haystack: &[u8], found: Range<usize>, template: &Template, dst: &mut Vec<u8>) {
+        // Give the inner matcher the field on its own, the same haystack it found the match in
+        let Some(field) = self.field_at(haystack, found.start) else {
+            return;
+        };
+        let found = found.start - field.start..found.end - field.start;
+        self.inner.expand(&haystack[field], found, template, dst);
+    }
+
+    fn lines_given_up(&self) -> usize {
+        self.inner.lines_given_up()
+    }
+    fn searches_lines(&self) -> bool {
+        self.inner.searches_lines()
+    }
+}
+
+/// The range of each of a CSV record's columns, excluding the quotes around quoted ones.
+/// Quoted values can hold commas and doubled quotes, but not line breaks, since every line is
+/// a record of its own here.
+fn csv_columns(record: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut start = Some(0);
+    std::iter::from_fn(move || {
+        let column_start = start?;
+        if record.get(column_start) == Some(&b'"') {
+            let mut end = column_start + 1;
+            while end < record.len() {
+                match (record[end], record.get(end + 1)) {
+                    (b'"', Some(b'"')) => end += 2,
+                    (b'"', _) => break,
+                    _ => end += 1,
+                }
+            }
+            let next = memchr::memchr(b',', &record[end.min(record.len())..]);
+            start = next.map(|comma| end + comma + 1);
+            return Some(column_start + 1..end.min(record.len()));
+        }
+        let end = memchr::memchr(b',', &record[column_start..]).map(|comma| column_start + comma);
+        start = end.map(|end| end + 1);
+        Some(column_start..end.unwrap_or(record.len()))
+    })
+}
+
+/// A quoted CSV value's contents without their doubled quotes.
+fn unquote_csv(value: &[u8]) -> Vec<u8> {
+    let mut unquoted = Vec::with_capacity(value.len());
+    let mut bytes = value.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte == b'"' && bytes.peek() == Some(&b'"') {
+            bytes.next();
+        }
+        unquoted.push(byte);
+    }
+    unquoted
+}
+
+/// Just enough of a JSON parser to find where a value is in a record, without building
+/// anything. Values it skips over aren't checked closely, so a record that's not quite valid
+/// JSON may still have its field found.
+struct JsonScanner<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> JsonScanner<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn field(mut self, path: &[String]) -> Option<Range<usize>> {
+        for segment in path {
+            self.skip_whitespace();
+            match self.peek()? {
+                b'{' => self.member(segment)?,
+                b'[' => self.element(segment.parse().ok()?)?,
+                _ => return None,
+            }
+        }
+        self.skip_whitespace();
+        let value = self.value()?;
+        if self.bytes[value.start] == b'"' {
+            Some(value.start + 1..value.end - 1)
+        } else {
+            Some(value)
+        }
+    }
+
+    /// Moves from the start of an object to the value of its member called `key`.
+    fn member(&mut self, key: &str) -> Option<()> {
+        self.position += 1;
+        loop {
+            self.skip_whitespace();
+            if self.peek()? == b'}' {
+                return None;
+            }
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            self.skip_whitespace();
+            if json_string_equals(&self.bytes[name], key) {
+                return Some(());
+            }
+            self.value()?;
+            self.skip_whitespace();
+            if self.peek()? == b',' {
+                self.position += 1;
+            }
+        }
+    }
+
+    /// Moves from the start of an array to its element at `index`.
+    fn element(&mut self, index: usize) -> Option<()> {
+        self.position += 1;
+        for _ in 0..index {
+            self.skip_whitespace();
+            if self.peek()? == b']' {
+                return None;
+            }
+            self.value()?;
+            self.skip_whitespace();
+            self.expect(b',')?;
+        }
+        self.skip_whitespace();
+        (self.peek()? != b']').then_some(())
+    }
+
+    /// Skips over a value, returning where it was.
+    fn value(&mut self) -> Option<Range<usize>> {
+        let start = self.position;
+        match self.peek()? {
+            b'"' => return self.string(),
+            b'{' | b'[' => {
+                let mut depth = 0;
+                loop {
+                    match self.peek()? {
+                        b'"' => {
+                            self.string()?;
+                            continue;
+                        }
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => depth -= 1,
+                        _ => {}
+                    }
+                    self.position += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                while self
+                    .peek()
+                    .is_some_and(|byte| !matches!(byte, b',' | b'}' | b']') && !is_space(byte))
+                {
+                    self.position += 1;
+                }
+            }
+        }
+        (self.position > start).then_some(start..self.position)
+    }
+
+    /// Skips over a string, returning where it was, quotes and all.
+    fn string(&mut self) -> Option<Range<usize>> {
+        let start = self.position;
+        self.expect(b'"')?;
+        loop {
+            match self.peek()? {
+                b'\\' => self.position += 2,
+                b'"' => {
+                    self.position += 1;
+                    return Some(start..self.position);
+                }
+                _ => self.position += 1,
+            }
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.peek()? == byte).then(|| self.position += 1)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(is_space) {
+            self.position += 1;
+        }
+    }
+}
+
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+/// Whether a JSON string, quotes and all, says `text` once its escapes are decoded.
+fn json_string_equals(string: &[u8], text: &str) -> bool {
+    if !string.contains(&b'\\') {
+        return &string[1..string.len() - 1] == text.as_bytes();
+    }
+    serde_json::from_slice::<String>(string).is_ok_and(|string| string == text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{LiteralMatcher, RegexMatcher};
+
+    fn locate<'a>(field: &Field, record: &'a str) -> Option<&'a str> {
+        field.locate(record.as_bytes()).map(|range| &record[range])
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let record =
+            r#"{"level": "error", "req": {"path": "/a,b", "tags": ["x", {"id": 7}]}, "n": -1.5}"#;
+        assert_eq!(locate(&Field::json("level"), record), Some("error"));
+        assert_eq!(locate(&Field::json("req.path"), record), Some("/a,b"));
+        assert_eq!(locate(&Field::json("req.tags.1.id"), record), Some("7"));
+        assert_eq!(
+            locate(&Field::json("req.tags.1"), record),
+            Some(r#"{"id": 7}"#)
+        );
+        assert_eq!(locate(&Field::json("n"), record), Some("-1.5"));
+        assert_eq!(locate(&Field::json("missing"), record), None);
+        assert_eq!(locate(&Field::json("req.tags.2"), record), None);
+        assert_eq!(locate(&Field::json("level.inner"), record), None);
+        assert_eq!(locate(&Field::json("level"), "not json"), None);
+    }
+
+    #[test]
+    fn test_json_keys_and_strings_with_escapes() {
+        let record = r#"{"a\"b": "x", "msg": "say \"hi\"", "tag": "ok"}"#;
+        assert_eq!(locate(&Field::json("a\"b"), record), Some("x"));
+        assert_eq!(locate(&Field::json("msg"), record), Some(r#"say \"hi\""#));
+        assert_eq!(locate(&Field::json("tag"), record), Some("ok"));
+    }
+
+    #[test]
+    fn test_csv_columns() {
+        let record = r#"1,"Smith, Jane","say ""hi""",,last"#;
+        assert_eq!(locate(&Field::Csv(0), record), Some("1"));
+        assert_eq!(locate(&Field::Csv(1), record), Some("Smith, Jane"));
+        assert_eq!(locate(&Field::Csv(2), record), Some(r#"say ""hi"""#));
+        assert_eq!(locate(&Field::Csv(3), record), Some(""));
+        assert_eq!(locate(&Field::Csv(4), record), Some("last"));
+        assert_eq!(locate(&Field::Csv(5), record), None);
+    }
+
+    #[test]
+    fn test_columns() {
+        assert_eq!("2".parse(), Ok(Column::Index(1)));
+        assert_eq!("name".parse(), Ok(Column::Name("name".to_string())));
+        assert!("0".parse::<Column>().is_err());
+        assert!("".parse::<Column>().is_err());
+
+        let header = b"id,\"full \"\"name\"\"\",email\r\n";
+        assert_eq!(Column::Name("email".to_string()).resolve(header), Some(2));
+        assert_eq!(
+            Column::Name("full \"name\"".to_string()).resolve(header),
+            Some(1)
+        );
+        assert_eq!(Column::Name("phone".to_string()).resolve(header), None);
+        assert_eq!(Column::Index(7).resolve(header), Some(7));
+    }
+
+    #[test]
+    fn test_field_matcher_only_matches_inside_the_field() {
+        let needle = LiteralMatcher::new("error", false).unwrap();
+        let matcher = FieldMatcher::new(needle, Field::json("level"));
+        let haystack =
+            b"{\"msg\": \"error\", \"level\": \"info\"}\n{\"msg\": \"ok\", \"level\": \"error\"}\n";
+        // The first line only mentions the needle outside the field
+        assert_eq!(matcher.find_at(haystack, 0), Some(58..63));
+        assert_eq!(matcher.find_at(haystack, 59), None);
+        assert!(!matcher.is_match(br#"{"msg": "error"}"#));
+        assert_eq!(
+            matcher.find_all(br#"{"level": "error error"}"#),
+            vec![11..16, 17..22]
+        );
+    }
+
+    #[test]
+    fn test_field_matcher_anchors_to_the_field() {
+        let needle = RegexMatcher::new("^(b+)$", false).unwrap();
+        let matcher = FieldMatcher::new(&needle, Field::Csv(1));
+        assert_eq!(matcher.find_at(b"a,bb,c\r\n", 0), Some(2..4));
+        assert!(!matcher.is_match(b"a,bbc,c"));
+
+        let template = Template::parse("<$1>");
+        let mut expanded = Vec::new();
+        matcher.expand(b"a,bb,c", 2..4, &template, &mut expanded);
+        assert_eq!(expanded, b"<bb>");
+    }
+}
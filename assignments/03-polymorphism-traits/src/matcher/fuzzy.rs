@@ -0,0 +1,202 @@
+use super::Matcher;
+use std::cmp::Reverse;
+use std::ops::Range;
+
+/// Finds approximate matches, as with `--fuzzy`: spans of a line that some needle can be turned
+/// into with at most `max_distance` single character insertions, deletions or substitutions
+/// (their Levenshtein distance).
+///
+/// Matches never run across a line break. When several overlapping spans are close enough, the
+/// one needing the fewest edits is reported, so that's what gets highlighted.
+pub struct FuzzyMatcher {
+    needles: Vec<Vec<u32>>,
+    max_distance: usize,
+    ignore_case: bool,
+}
+
+impl FuzzyMatcher {
+    pub fn new(needle: &str, max_distance: usize, ignore_case: bool) -> Self {
+        Self::any_of(&[needle], max_distance, ignore_case)
+    }
+
+    /// Matches whichever of `needles` is found first. With no needles at all, nothing matches.
+    pub fn any_of(needles: &[impl AsRef<str>], max_distance: usize, ignore_case: bool) -> Self {
+        let needles = needles
+            .iter()
+            .map(|needle| {
+                let chars = needle.as_ref().chars();
+                chars.map(|c| fold(c, ignore_case)).collect()
+            })
+            .collect();
+        Self {
+            needles,
+            max_distance,
+            ignore_case,
+        }
+    }
+
+    /// Finds the best span of `units` for `needle` near the first place it's close enough,
+    /// returning its start and end indices into `units` and its distance.
+    fn find_in(&self, needle: &[u32], units: &[(usize, u32)]) -> Option<(usize, usize, usize)> {
+        // Sellers' algorithm: row[i] holds the fewest edits turning the first i characters of
+        // the needle into some span ending at the current position, and where that span starts
+        let mut row: Vec<(usize, usize)> = (0..=needle.len()).map(|i| (i, 0)).collect();
+        let mut best: Option<(usize, usize, usize)> = None;
+        for end in 0..=units.len() {
+            if end > 0 {
+                let unit = units[end - 1].1;
+                let mut diagonal = row[0];
+                // The needle can start anywhere, so matching nothing of it costs nothing
+                row[0] = (0, end);
+                for i in 1..=needle.len() {
+                    let substitute = (diagonal.0 + usize::from(needle[i - 1] != unit), diagonal.1);
+                    let insert = (row[i].0 + 1, row[i].1);
+                    let delete = (row[i - 1].0 + 1, row[i - 1].1);
+                    diagonal = row[i];
+                    row[i] = [substitute, insert, delete]
+                        .into_iter()
+                        .min_by_key(|&(cost, start)| (cost, Reverse(start)))
+                        .unwrap();
+                }
+            }
+            let (distance, start) = row[needle.len()];
+            // Only a span overlapping the best so far can take its place, so a later match that's
+            // closer doesn't hide this one
+            let overlaps = |(best_start, best_end, _): (usize, usize, usize)| {
+                start < best_end || start <= best_start
+            };
+            if distance <= self.max_distance
+                && best.is_none_or(|best| distance <= best.2 && overlaps(best))
+            {
+                best = Some((start, end, distance));
+            }
+            // An overlapping span starts before the best one ends, and with insertions can be
+            // up to max_distance characters longer than the needle
+            let reach = needle.len() + self.max_distance;
+            if best.is_some_and(|(_, best_end, _)| end >= best_end + reach) {
+                break;
+            }
+        }
+        best
+    }
+}
+
+impl Matcher for FuzzyMatcher {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        let mut line_start = at;
+        while line_start <= haystack.len() {
+            let line_end = memchr::memchr(b'\n', &haystack[line_start..])
+                .map_or(haystack.len(), |newline| line_start + newline);
+            let line = &haystack[line_start..line_end];
+            let units = units(line, self.ignore_case);
+            let found = self
+                .needles
+                .iter()
+                .filter_map(|needle| self.find_in(needle, &units))
+                .min_by_key(|&(start, end, distance)| (start, distance, Reverse(end)));
+            if let Some((start, end, _)) = found {
+                let offset = |index: usize| units.get(index).map_or(line.len(), |unit| unit.0);
+                return Some(line_start + offset(start)..line_start + offset(end));
+            }
+            line_start = line_end + 1;
+        }
+        None
+    }
+}
+
+/// Bytes that aren't valid UTF-8 are given values past the last code point, so they only ever
+/// match themselves.
+const INVALID_BYTE: u32 = char::MAX as u32 + 1;
+
+fn fold(c: char, ignore_case: bool) -> u32 {
+    let c = if ignore_case {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c
+    };
+    c as u32
+}
+
+/// Splits `line` into the characters distances are counted in, along with the byte offset each
+/// one starts at.
+fn units(line: &[u8], ignore_case: bool) -> Vec<(usize, u32)> {
+    let mut units = Vec::with_capacity(line.len());
+    let mut offset = 0;
+    for chunk in line.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            units.push((offset, fold(c, ignore_case)));
+            offset += c.len_utf8();
+        }
+        for &byte in chunk.invalid() {
+            units.push((offset, INVALID_BYTE + u32::from(byte)));
+            offset += 1;
+        }
+    }
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_matcher() {
+        let matcher = FuzzyMatcher::new("hello", 1, false);
+        assert_eq!(matcher.find_at(b"say helo world", 0), Some(4..8));
+        assert_eq!(matcher.find_at(b"say hallo world", 0), Some(4..9));
+        assert_eq!(matcher.find_at(b"say heLLo world", 0), None);
+        assert!(!matcher.is_match(b"help"));
+    }
+
+    #[test]
+    fn test_fuzzy_matcher_prefers_the_closest_span() {
+        // "hell" is only one edit away, but "hello" itself is right there
+        let matcher = FuzzyMatcher::new("hello", 1, false);
+        assert_eq!(matcher.find_all(b"hello, hello"), vec![0..5, 7..12]);
+
+        let matcher = FuzzyMatcher::new("color", 1, false);
+        assert_eq!(matcher.find_at(b"the colour red", 0), Some(4..10));
+    }
+
+    #[test]
+    fn test_fuzzy_matcher_with_insertions() {
+        // Spans with insertions run longer than the needle
+        let matcher = FuzzyMatcher::new("hello", 1, false);
+        assert_eq!(matcher.find_at(b"say hexllo", 0), Some(4..10));
+        let matcher = FuzzyMatcher::new("hello", 2, false);
+        assert_eq!(matcher.find_at(b"say hel-l-o", 0), Some(4..11));
+        // A closer match further on is a separate match, not a better version of this one
+        assert_eq!(matcher.find_all(b"hello hello"), vec![0..5, 6..11]);
+        assert_eq!(matcher.find_all(b"helo xx hello"), vec![0..4, 8..13]);
+    }
+
+    #[test]
+    fn test_fuzzy_matcher_stops_at_line_breaks() {
+        let matcher = FuzzyMatcher::new("hello", 1, false);
+        assert_eq!(matcher.find_at(b"hel\nlo", 0), None);
+        assert_eq!(matcher.find_at(b"hel\nhelo", 0), Some(4..8));
+        assert_eq!(matcher.find_at(b"helo\nhelo", 1), Some(5..9));
+    }
+
+    #[test]
+    fn test_fuzzy_matcher_counts_characters() {
+        let matcher = FuzzyMatcher::new("naive", 1, false);
+        assert_eq!(matcher.find_at("so naïve".as_bytes(), 0), Some(3..9));
+
+        let matcher = FuzzyMatcher::new("CAFÉ", 0, true);
+        assert_eq!(matcher.find_at("un café".as_bytes(), 0), Some(3..8));
+
+        // Invalid bytes count as one character each
+        let matcher = FuzzyMatcher::new("cafe", 1, false);
+        assert_eq!(matcher.find_at(b"caf\xe9", 0), Some(0..4));
+    }
+
+    #[test]
+    fn test_fuzzy_matcher_any_of() {
+        let matcher = FuzzyMatcher::any_of(&["apple", "banana"], 1, false);
+        assert_eq!(matcher.find_all(b"bananna and aple"), vec![0..7, 12..16]);
+
+        let matcher = FuzzyMatcher::any_of(&[] as &[&str], 1, false);
+        assert!(!matcher.is_match(b"anything"));
+    }
+}
@@ -0,0 +1,329 @@
+use super::Matcher;
+use crate::replace::{Group, Template};
+use anyhow::Result;
+use fancy_regex::{Regex, RegexBuilder};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many times a search can backtrack before giving up. Enough for any reasonable pattern,
+/// while one that backtracks catastrophically, like `(a+)+\1` against a long run of `a`s, gives
+/// up in well under a second instead of running for ages.
+pub const STEP_LIMIT: usize = 1_000_000;
+
+/// Matches a Perl-style regular expression using `fancy-regex`, a backtracking engine, as with
+/// `-P`. It supports what the `regex` crate can't, like look-around and backreferences.
+///
+/// Backtracking can take exponentially long, so searches give up after [`STEP_LIMIT`] steps.
+/// When one does, the lines it covered are searched again one at a time, as is the rest of the
+/// haystack in `find_all`, and any line that's still too much is treated as not matching and
+/// counted in [`Matcher::lines_given_up`].
+///
+/// Only valid UTF-8 can be searched, so invalid bytes never match and matches don't span them.
+pub struct PcreMatcher {
+    regex: Regex,
+    /// With several patterns, each one on its own as well, to number the groups of a match
+    /// within the pattern that found it.
+    patterns: Vec<Regex>,
+    given_up: AtomicUsize,
+}
+
+impl PcreMatcher {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self> {
+        Self::any_of(&[pattern], ignore_case)
+    }
+
+    /// Matches any one of `patterns`. With no patterns at all, nothing matches.
+    pub fn any_of(patterns: &[impl AsRef<str>], ignore_case: bool) -> Result<Self> {
+        let alternation = if patterns.is_empty() {
+            // An empty class, which can never match
+            r"[^\s\S]".to_string()
+        } else {
+            let groups: Vec<_> = patterns
+                .iter()
+                .map(|pattern| format!("(?:{})", pattern.as_ref()))
+                .collect();
+            groups.join("|")
+        };
+        // Groups are numbered across the whole alternation. Wrapping each pattern in a group of
+        // its own, as RegexMatcher does, would throw its backreferences off, so keep each one
+        // apart to look the groups up in instead
+        let patterns = if patterns.len() > 1 {
+            patterns
+                .iter()
+                .map(|pattern| build(pattern.as_ref(), ignore_case))
+                .collect::<Result<_>>()?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            regex: build(&alternation, ignore_case)?,
+            patterns,
+            given_up: AtomicUsize::new(0),
+        })
+    }
+
+    /// Runs `search` over `text` from `at`. If it gives up, runs it again over one line of
+    /// `text` at a time instead, returning the first result and how many lines it gave up on.
+    ///
+    /// `per_line` is set once a search has given up, and from then on `search` goes straight to
+    /// one line at a time. Passing the same flag to every search of a haystack keeps the rest of
+    /// it from being tried whole, and given up on, all over again for each match.
+    fn search<'t, T>(
+        &self,
+        text: &'t str,
+        at: usize,
+        per_line: &mut bool,
+        search: impl Fn(&'t str, usize) -> fancy_regex::Result<Option<T>>,
+    ) -> (Option<T>, usize) {
+        if !*per_line {
+            match search(text, at) {
+                Ok(found) => return (found, 0),
+                Err(_) => *per_line = true,
+            }
+            // What's left was only one line, so it's already been given up on
+            if !text[at..].contains('\n') {
+                return (None, 1);
+            }
+        }
+        let mut given_up = 0;
+        let mut line_start = text[..at].rfind('\n').map_or(0, |newline| newline + 1);
+        while line_start <= text.len() {
+            let line_end = text[line_start..]
+                .find('\n')
+                .map_or(text.len(), |newline| line_start + newline);
+            // What comes before the line is left in, so look-behind still sees it
+            match search(&text[..line_end], at.max(line_start)) {
+                Ok(Some(found)) => return (Some(found), given_up),
+                Ok(None) => {}
+                Err(_) => given_up += 1,
+            }
+            line_start = line_end + 1;
+        }
+        (None, given_up)
+    }
+}
+
+fn build(pattern: &str, ignore_case: bool) -> Result<Regex> {
+    // Like RegexMatcher, ^ and $ mean the start and end of a line
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .multi_line(true)
+        .crlf(true)
+        .backtrack_limit(STEP_LIMIT)
+        .build()?;
+    Ok(regex)
+}
+
+/// The valid UTF-8 runs of `haystack`, with where each one starts.
+fn valid_utf8(haystack: &[u8]) -> impl Iterator<Item = (usize, &str)> {
+    let mut offset = 0;
+    let chunks = haystack.utf8_chunks().map(move |chunk| {
+        let start = offset;
+        offset += chunk.valid().len() + chunk.invalid().len();
+        (start, chunk.valid())
+    });
+    // An empty haystack has no chunks, but a pattern that matches nothing still matches it
+    chunks.chain(haystack.is_empty().then_some((0, "")))
+}
+
+impl Matcher for PcreMatcher {
+    fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+        for (start, text) in valid_utf8(haystack) {
+            if start + text.len() < at {
+                continue;
+            }
+            // The search has to start on a character boundary
+            let mut pos = at.saturating_sub(start);
+            while !text.is_char_boundary(pos) {
+                pos += 1;
+            }
+            let (found, given_up) = self.search(text, pos, &mut false, |text, pos| {
+                let found = self.regex.find_from_pos(text, pos)?;
+                Ok(found.map(|m| m.range()))
+            });
+            self.given_up.fetch_add(given_up, Ordering::Relaxed);
+            if let Some(found) = found {
+                return Some(start + found.start..start + found.end);
+            }
+        }
+        None
+    }
+
+    fn find_all(&self, haystack: &[u8]) -> Vec<Range<usize>> {
+        // Unlike the default, this goes over the haystack's UTF-8 once rather than for every
+        // match, and one give-up covers the whole haystack
+        let mut per_line = false;
+        let mut matches = Vec::new();
+        for (start, text) in valid_utf8(haystack) {
+            let mut pos = 0;
+            while pos <= text.len() {
+                let (found, given_up) = self.search(text, pos, &mut per_line, |text, pos| {
+                    let found = self.regex.find_from_pos(text, pos)?;
+                    Ok(found.map(|m| m.range()))
+                });
+                self.given_up.fetch_add(given_up, Ordering::Relaxed);
+                let Some(found) = found else {
+                    break;
+                };
+                // An empty match would otherwise be found again at the same spot
+                pos = if found.is_empty() {
+                    text[found.end..]
+                        .chars()
+                        .next()
+                        .map_or(text.len() + 1, |c| found.end + c.len_utf8())
+                } else {
+                    found.end
+                };
+                matches.push(start + found.start..start + found.end);
+            }
+        }
+        matches
+    }
+
+    fn searches_lines(&self) -> bool {
+        // Every search checks that the rest of the haystack is valid UTF-8 before it starts
+        true
+    }
+
+    fn expand(&self, haystack: &[u8], found: Range<usize>, template: &Template, dst: &mut Vec<u8>) {
+        let Some((start, text)) = valid_utf8(haystack)
+            .find(|(start, text)| (*start..=start + text.len()).contains(&found.start))
+        else {
+            return;
+        };
+        // Searching again from the start of the match finds the same match, with its groups.
+        // Lines given up on were already counted when the match was found.
+        let pos = found.start - start;
+        let captures = |regex: &Regex| {
+            let (captures, _) = self.search(text, pos, &mut false, |text, pos| {
+                regex.captures_from_pos(text, pos)
+            });
+            captures
+        };
+        // The alternation takes the first pattern that matches where the match starts
+        let captures = if self.patterns.is_empty() {
+            captures(&self.regex)
+        } else {
+            self.patterns.iter().find_map(|regex| {
+                captures(regex).filter(|captures| captures.get(0).is_some_and(|m| m.start() == pos))
+            })
+        };
+        let Some(captures) = captures else {
+            return;
+        };
+        template.expand(dst, |group| {
+            match group {
+                Group::Index(index) => captures.get(*index),
+                Group::Name(name) => captures.name(name),
+            }
+            .map(|m| m.as_str().as_bytes())
+        });
+    }
+
+    fn lines_given_up(&self) -> usize {
+        self.given_up.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_look_around_and_backreferences() {
+        let matcher = PcreMatcher::new(r"foo(?=bar)", false).unwrap();
+        assert_eq!(matcher.find_at(b"foobaz foobar", 0), Some(7..10));
+        let matcher = PcreMatcher::new(r"(?<!\$)\b\d+", false).unwrap();
+        assert_eq!(matcher.find_all(b"$12 and 34"), vec![8..10]);
+        let matcher = PcreMatcher::new(r"\b(\w+) \1\b", false).unwrap();
+        assert_eq!(matcher.find_at(b"it is is here", 0), Some(3..8));
+        assert!(!matcher.is_match(b"it is this"));
+    }
+
+    #[test]
+    fn test_anchors_and_case() {
+        let matcher = PcreMatcher::any_of(&["^b", "C$"], true).unwrap();
+        assert_eq!(matcher.find_all(b"ab\nbc\r\n"), vec![3..4, 4..5]);
+        assert!(!PcreMatcher::any_of(&[] as &[&str], false)
+            .unwrap()
+            .is_match(b"anything"));
+        assert!(PcreMatcher::new("^$", false).unwrap().is_match(b""));
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let matcher = PcreMatcher::new(r"(?<=a)b+", false).unwrap();
+        assert_eq!(matcher.find_all(b"ab\xffab\xffbb"), vec![1..2, 4..5]);
+        // Starting in the middle of a character skips to the next one
+        let matcher = PcreMatcher::new(r"\w", false).unwrap();
+        assert_eq!(matcher.find_at("é!x".as_bytes(), 1), Some(3..4));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let matcher = PcreMatcher::new(r"^(a+)+\1$", false).unwrap();
+        let catastrophic = format!("aaaa\n{}b\naaaa\n", "a".repeat(40));
+        // Only the line that's too much is given up on, the others are still searched
+        assert_eq!(matcher.find_all(catastrophic.as_bytes()).len(), 2);
+        assert_eq!(matcher.lines_given_up(), 1);
+        assert_eq!(PcreMatcher::new("(a)", false).unwrap().lines_given_up(), 0);
+    }
+
+    #[test]
+    fn test_expand_numbers_groups_per_pattern() {
+        let matcher = PcreMatcher::any_of(&[r"(\w)\1", r"a(\d)", r"b(\d)"], false).unwrap();
+        let haystack = b"cc a1 b2";
+        let expanded: Vec<_> = matcher
+            .find_all(haystack)
+            .into_iter()
+            .map(|found| {
+                let mut dst = Vec::new();
+                matcher.expand(haystack, found, &Template::parse("[$1]"), &mut dst);
+                String::from_utf8(dst).unwrap()
+            })
+            .collect();
+        // The backreference still refers to the group in its own pattern
+        assert_eq!(expanded, ["[c]", "[1]", "[2]"]);
+    }
+
+    #[test]
+    fn test_per_line_after_giving_up() {
+        let matcher = PcreMatcher::new(r"^(a+)+\1$", false).unwrap();
+        let catastrophic = format!("{}b", "a".repeat(40));
+        let text = format!("{catastrophic}\naaaa\n{catastrophic}\naaaa\n");
+        // Runs a search, noting the length of the text given to each attempt
+        let search = |at, per_line: &mut bool| {
+            let attempts = RefCell::new(Vec::new());
+            let (found, given_up) = matcher.search(&text, at, per_line, |text, pos| {
+                attempts.borrow_mut().push(text.len());
+                Ok(matcher.regex.find_from_pos(text, pos)?.map(|m| m.range()))
+            });
+            (found, given_up, attempts.into_inner())
+        };
+
+        let mut per_line = false;
+        let (found, given_up, attempts) = search(0, &mut per_line);
+        assert_eq!((found, given_up), (Some(42..46), 1));
+        assert_eq!(attempts, [text.len(), 41, 46]);
+        assert!(per_line);
+        // The rest of the text is never tried whole again
+        let (found, given_up, attempts) = search(46, &mut per_line);
+        assert_eq!((found, given_up), (Some(89..93), 1));
+        assert!(!attempts.contains(&text.len()));
+
+        assert_eq!(matcher.find_all(text.as_bytes()), vec![42..46, 89..93]);
+        assert_eq!(matcher.lines_given_up(), 2);
+    }
+
+    #[test]
+    fn test_expand() {
+        let matcher = PcreMatcher::new(r"(?<key>\w+)=(\w+)(?=;)", false).unwrap();
+        let haystack = b"a=1 b=2;";
+        let found = matcher.find_at(haystack, 0).unwrap();
+        assert_eq!(found, 4..7);
+        let mut dst = Vec::new();
+        matcher.expand(haystack, found, &Template::parse("$2:${key}"), &mut dst);
+        assert_eq!(dst, b"2:b");
+    }
+}
@@ -0,0 +1,920 @@
+mod json;
+mod theme;
+
+pub use json::JsonOutput;
+pub use theme::{ColorChoice, Theme};
+
+use crate::search::Stats;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use theme::paint;
+
+/// A line handed to a sink, along with where it was found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line<'a> {
+    /// The file the line came from, or `None` for stdin.
+    pub path: Option<&'a Path>,
+    /// The 1-based line number.
+    pub number: u64,
+    /// The byte offset of the start of the line within its input.
+    pub offset: u64,
+    /// The line itself, without its terminator.
+    pub content: &'a [u8],
+}
+
+/// Somewhere for a search to send the lines it found, like a printer or a collector. An error
+/// from any method stops the search it came from.
+pub trait Sink {
+    /// Called before a file (or stdin) is searched.
+    fn begin(&mut self, _path: Option<&Path>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Prints one matching line. `matches` holds the byte ranges of the needle within it.
+    fn write_line(&mut self, line: &Line, matches: &[Range<usize>]) -> io::Result<()>;
+
+    /// Prints a line of context surrounding a match.
+    fn write_context(&mut self, line: &Line) -> io::Result<()>;
+
+    /// Called instead of `write_line` when a binary input has a selected line, since its lines
+    /// aren't fit to print. Only called once per input.
+    fn binary_match(&mut self, _path: Option<&Path>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Marks the start of a new group of context lines. Only needs to print anything if an
+    /// earlier group was already printed.
+    fn write_separator(&mut self) -> io::Result<()>;
+
+    /// Called once a file (or stdin) has been searched, with what was found in it.
+    fn end(&mut self, _path: Option<&Path>, _stats: &Stats) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once everything has been searched.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether the sink uses how many lines were selected in each input, as passed to `end`.
+    /// Binary inputs are otherwise only searched as far as their first selected line.
+    fn counts_lines(&self) -> bool {
+        false
+    }
+}
+
+/// Settings shared by the text outputs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PrintOptions {
+    /// Prefix each line with the path of the file it came from.
+    pub with_filename: bool,
+    /// Print each match on a line of its own, instead of the whole line it was found on.
+    pub only_matching: bool,
+    /// Prefix each line with its 1-based line number.
+    pub line_number: bool,
+    /// Prefix each line with the 1-based column of its first match.
+    pub column: bool,
+    /// Prefix each line with the byte offset of its start within the input, or of the match
+    /// itself with `only_matching`.
+    pub byte_offset: bool,
+}
+
+const SEPARATOR: &[u8] = b"--";
+
+/// Writes the `path:` (or `path-` for context lines) prefix in front of a line, if wanted.
+fn write_path(
+    writer: &mut impl Write,
+    options: &PrintOptions,
+    theme: &Theme,
+    path: Option<&Path>,
+    delimiter: u8,
+) -> io::Result<()> {
+    match path {
+        Some(path) if options.with_filename => {
+            paint(writer, &theme.path, path.display().to_string().as_bytes())?;
+            paint(writer, &theme.separator, &[delimiter])
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Writes everything that goes in front of a line, in the `path:line:column:offset:` order
+/// editors understand (with `-` instead of `:` for context lines), skipping what wasn't asked for.
+/// `at` is where the match being pointed at starts in the line, if there is one.
+fn write_prefix(
+    writer: &mut impl Write,
+    options: &PrintOptions,
+    theme: &Theme,
+    line: &Line,
+    at: Option<usize>,
+    delimiter: u8,
+) -> io::Result<()> {
+    write_path(writer, options, theme, line.path, delimiter)?;
+    let mut write_number = |style: &str, number: u64| {
+        paint(writer, style, number.to_string().as_bytes())?;
+        paint(writer, &theme.separator, &[delimiter])
+    };
+    if options.line_number {
+        write_number(&theme.line_number, line.number)?;
+    }
+    if let Some(at) = at.filter(|_| options.column) {
+        write_number(&theme.line_number, at as u64 + 1)?;
+    }
+    if options.byte_offset {
+        let offset = match at {
+            Some(at) if options.only_matching => line.offset + at as u64,
+            _ => line.offset,
+        };
+        write_number(&theme.byte_offset, offset)?;
+    }
+    Ok(())
+}
+
+/// Prints lines as they were read, apart from their line endings. Lines reach sinks without
+/// their terminators, so each is printed with a `\n` after it, and CRLF endings come out as LF.
+pub struct PlainOutput<W: Write> {
+    writer: W,
+    options: PrintOptions,
+    wrote_any: bool,
+}
+
+impl<W: Write> PlainOutput<W> {
+    pub fn new(writer: W, options: PrintOptions) -> Self {
+        Self {
+            writer,
+            options,
+            wrote_any: false,
+        }
+    }
+}
+
+impl<W: Write> Sink for PlainOutput<W> {
+    fn write_line(&mut self, line: &Line, matches: &[Range<usize>]) -> io::Result<()> {
+        self.wrote_any = true;
+        if self.options.only_matching {
+            for found in matches.iter().filter(|found| !found.is_empty()) {
+                let at = Some(found.start);
+                write_prefix(
+                    &mut self.writer,
+                    &self.options,
+                    &Theme::NONE,
+                    line,
+                    at,
+                    b':',
+                )?;
+                self.writer.write_all(&line.content[found.clone()])?;
+                self.writer.write_all(b"\n")?;
+            }
+            return Ok(());
+        }
+        let at = matches.first().map(|found| found.start);
+        write_prefix(
+            &mut self.writer,
+            &self.options,
+            &Theme::NONE,
+            line,
+            at,
+            b':',
+        )?;
+        self.writer.write_all(line.content)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn write_context(&mut self, line: &Line) -> io::Result<()> {
+        self.wrote_any = true;
+        write_prefix(
+            &mut self.writer,
+            &self.options,
+            &Theme::NONE,
+            line,
+            None,
+            b'-',
+        )?;
+        self.writer.write_all(line.content)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn binary_match(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.wrote_any = true;
+        writeln!(self.writer, "Binary file {} matches", display_name(path))
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        if self.wrote_any {
+            self.writer.write_all(SEPARATOR)?;
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Prints lines with their matches, paths, line numbers and separators colored by a theme.
+pub struct ColoredOutput<W: Write> {
+    writer: W,
+    options: PrintOptions,
+    theme: Theme,
+    wrote_any: bool,
+}
+
+impl<W: Write> ColoredOutput<W> {
+    pub fn new(writer: W, options: PrintOptions, theme: Theme) -> Self {
+        Self {
+            writer,
+            options,
+            theme,
+            wrote_any: false,
+        }
+    }
+}
+
+impl<W: Write> Sink for ColoredOutput<W> {
+    fn write_line(&mut self, line: &Line, matches: &[Range<usize>]) -> io::Result<()> {
+        self.wrote_any = true;
+        let theme = &self.theme;
+        if self.options.only_matching {
+            for found in matches.iter().filter(|found| !found.is_empty()) {
+                let at = Some(found.start);
+                write_prefix(&mut self.writer, &self.options, theme, line, at, b':')?;
+                paint(
+                    &mut self.writer,
+                    &theme.selected_match,
+                    &line.content[found.clone()],
+                )?;
+                self.writer.write_all(b"\n")?;
+            }
+            return Ok(());
+        }
+        let at = matches.first().map(|found| found.start);
+        write_prefix(&mut self.writer, &self.options, theme, line, at, b':')?;
+        let mut printed = 0;
+        for found in matches.iter().filter(|found| !found.is_empty()) {
+            let before = &line.content[printed..found.start];
+            paint(&mut self.writer, &theme.selected_line, before)?;
+            let needle = &line.content[found.clone()];
+            paint(&mut self.writer, &theme.selected_match, needle)?;
+            printed = found.end;
+        }
+        paint(
+            &mut self.writer,
+            &theme.selected_line,
+            &line.content[printed..],
+        )?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn write_context(&mut self, line: &Line) -> io::Result<()> {
+        self.wrote_any = true;
+        let theme = &self.theme;
+        write_prefix(&mut self.writer, &self.options, theme, line, None, b'-')?;
+        paint(&mut self.writer, &theme.context_line, line.content)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn binary_match(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.wrote_any = true;
+        writeln!(self.writer, "Binary file {} matches", display_name(path))
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        if self.wrote_any {
+            paint(&mut self.writer, &self.theme.separator, SEPARATOR)?;
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Prints how many lines were selected in each input instead of the lines themselves, as with
+/// `--count`.
+pub struct CountOutput<W: Write> {
+    writer: W,
+    options: PrintOptions,
+}
+
+impl<W: Write> CountOutput<W> {
+    pub fn new(writer: W, options: PrintOptions) -> Self {
+        Self { writer, options }
+    }
+}
+
+impl<W: Write> Sink for CountOutput<W> {
+    fn write_line(&mut self, _line: &Line, _matches: &[Range<usize>]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_context(&mut self, _line: &Line) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn end(&mut self, path: Option<&Path>, stats: &Stats) -> io::Result<()> {
+        write_path(&mut self.writer, &self.options, &Theme::NONE, path, b':')?;
+        writeln!(self.writer, "{}", stats.matched_lines)
+    }
+
+    fn counts_lines(&self) -> bool {
+        true
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Prints only the paths of the inputs that had a selected line, as with `-l`, or of those that
+/// didn't, as with `-L`.
+pub struct FileListOutput<W: Write> {
+    writer: W,
+    without_match: bool,
+}
+
+impl<W: Write> FileListOutput<W> {
+    pub fn new(writer: W, without_match: bool) -> Self {
+        Self {
+            writer,
+            without_match,
+        }
+    }
+}
+
+impl<W: Write> Sink for FileListOutput<W> {
+    fn write_line(&mut self, _line: &Line, _matches: &[Range<usize>]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_context(&mut self, _line: &Line) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn end(&mut self, path: Option<&Path>, stats: &Stats) -> io::Result<()> {
+        if (stats.matched_lines > 0) == self.without_match {
+            return Ok(());
+        }
+        writeln!(self.writer, "{}", display_name(path))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// What to call an input in messages: its path, or `(standard input)` for stdin.
+fn display_name(path: Option<&Path>) -> Cow<'_, str> {
+    match path {
+        Some(path) => path.to_string_lossy(),
+        None => Cow::Borrowed("(standard input)"),
+    }
+}
+
+// Lets sinks chosen at runtime be wrapped in one another
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    fn begin(&mut self, path: Option<&Path>) -> io::Result<()> {
+        (**self).begin(path)
+    }
+
+    fn write_line(&mut self, line: &Line, matches: &[Range<usize>]) -> io::Result<()> {
+        (**self).write_line(line, matches)
+    }
+
+    fn write_context(&mut self, line: &Line) -> io::Result<()> {
+        (**self).write_context(line)
+    }
+
+    fn binary_match(&mut self, path: Option<&Path>) -> io::Result<()> {
+        (**self).binary_match(path)
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        (**self).write_separator()
+    }
+
+    fn end(&mut self, path: Option<&Path>, stats: &Stats) -> io::Result<()> {
+        (**self).end(path, stats)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+
+    fn counts_lines(&self) -> bool {
+        (**self).counts_lines()
+    }
+}
+
+/// Passes everything on to another sink, adding up what was found in every input on the way,
+/// for deciding the exit status.
+pub struct TallyingOutput<S> {
+    inner: S,
+    totals: Stats,
+}
+
+impl<S: Sink> TallyingOutput<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            totals: Stats::default(),
+        }
+    }
+
+    /// The stats of every input that's ended so far, added up.
+    pub fn totals(&self) -> &Stats {
+        &self.totals
+    }
+}
+
+impl<S: Sink> Sink for TallyingOutput<S> {
+    fn begin(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.inner.begin(path)
+    }
+
+    fn write_line(&mut self, line: &Line, matches: &[Range<usize>]) -> io::Result<()> {
+        self.inner.write_line(line, matches)
+    }
+
+    fn write_context(&mut self, line: &Line) -> io::Result<()> {
+        self.inner.write_context(line)
+    }
+
+    fn binary_match(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.inner.binary_match(path)
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        self.inner.write_separator()
+    }
+
+    fn end(&mut self, path: Option<&Path>, stats: &Stats) -> io::Result<()> {
+        self.totals.add(stats);
+        self.inner.end(path, stats)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()
+    }
+
+    fn counts_lines(&self) -> bool {
+        self.inner.counts_lines()
+    }
+}
+
+/// Holds on to everything written to it so it can be replayed into another output later. This
+/// lets files be searched in parallel while their results are still printed in order.
+#[derive(Debug, Default)]
+pub struct BufferedOutput {
+    events: Vec<Event>,
+    counts_lines: bool,
+}
+
+#[derive(Debug)]
+enum Event {
+    Begin(Option<PathBuf>),
+    Line(OwnedLine, Vec<Range<usize>>),
+    Context(OwnedLine),
+    BinaryMatch(Option<PathBuf>),
+    Separator,
+    End(Option<PathBuf>, Stats),
+}
+
+#[derive(Debug)]
+struct OwnedLine {
+    path: Option<PathBuf>,
+    number: u64,
+    offset: u64,
+    content: Vec<u8>,
+}
+
+impl OwnedLine {
+    fn new(line: &Line) -> Self {
+        Self {
+            path: line.path.map(Path::to_path_buf),
+            number: line.number,
+            offset: line.offset,
+            content: line.content.to_vec(),
+        }
+    }
+
+    fn as_line(&self) -> Line<'_> {
+        Line {
+            path: self.path.as_deref(),
+            number: self.number,
+            offset: self.offset,
+            content: &self.content,
+        }
+    }
+}
+
+impl BufferedOutput {
+    /// Buffers results for an output whose [`Sink::counts_lines`] is `counts_lines`.
+    pub fn new(counts_lines: bool) -> Self {
+        Self {
+            events: Vec::new(),
+            counts_lines,
+        }
+    }
+
+    /// Writes everything buffered so far to `output`, in the order it was received.
+    pub fn replay(&self, output: &mut dyn Sink) -> io::Result<()> {
+        for event in &self.events {
+            match event {
+                Event::Begin(path) => output.begin(path.as_deref())?,
+                Event::Line(line, matches) => output.write_line(&line.as_line(), matches)?,
+                Event::Context(line) => output.write_context(&line.as_line())?,
+                Event::BinaryMatch(path) => output.binary_match(path.as_deref())?,
+                Event::Separator => output.write_separator()?,
+                Event::End(path, stats) => output.end(path.as_deref(), stats)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for BufferedOutput {
+    fn begin(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.events.push(Event::Begin(path.map(Path::to_path_buf)));
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &Line, matches: &[Range<usize>]) -> io::Result<()> {
+        self.events
+            .push(Event::Line(OwnedLine::new(line), matches.to_vec()));
+        Ok(())
+    }
+
+    fn write_context(&mut self, line: &Line) -> io::Result<()> {
+        self.events.push(Event::Context(OwnedLine::new(line)));
+        Ok(())
+    }
+
+    fn binary_match(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.events
+            .push(Event::BinaryMatch(path.map(Path::to_path_buf)));
+        Ok(())
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        self.events.push(Event::Separator);
+        Ok(())
+    }
+
+    fn end(&mut self, path: Option<&Path>, stats: &Stats) -> io::Result<()> {
+        self.events
+            .push(Event::End(path.map(Path::to_path_buf), *stats));
+        Ok(())
+    }
+
+    fn counts_lines(&self) -> bool {
+        self.counts_lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line<'a>(path: Option<&'a str>, content: &'a str) -> Line<'a> {
+        Line {
+            path: path.map(Path::new),
+            number: 1,
+            offset: 0,
+            content: content.as_bytes(),
+        }
+    }
+
+    const WITH_FILENAME: PrintOptions = PrintOptions {
+        with_filename: true,
+        only_matching: false,
+        line_number: false,
+        column: false,
+        byte_offset: false,
+    };
+
+    /// A theme that only highlights matches, in plain red.
+    fn red_matches() -> Theme {
+        Theme {
+            selected_match: "31".to_string(),
+            ..Theme::NONE
+        }
+    }
+
+    #[test]
+    fn test_plain_output() {
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, WITH_FILENAME);
+        output
+            .write_line(&line(None, "hello world"), &[0..5, 6..11])
+            .unwrap();
+        output
+            .write_line(&line(Some("src/main.rs"), "fn main"), &[])
+            .unwrap();
+        assert_eq!(buffer, b"hello world\nsrc/main.rs:fn main\n");
+    }
+
+    #[test]
+    fn test_plain_output_without_filename() {
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, PrintOptions::default());
+        output
+            .write_line(&line(Some("src/main.rs"), "fn main"), &[])
+            .unwrap();
+        assert_eq!(buffer, b"fn main\n");
+    }
+
+    #[test]
+    fn test_colored_output() {
+        let mut buffer = Vec::new();
+        let mut output = ColoredOutput::new(&mut buffer, WITH_FILENAME, red_matches());
+        output
+            .write_line(&line(None, "one fish two fish"), &[4..8, 13..17])
+            .unwrap();
+        assert_eq!(buffer, b"one \x1b[31mfish\x1b[0m two \x1b[31mfish\x1b[0m\n");
+    }
+
+    #[test]
+    fn test_colored_output_with_path_and_no_matches() {
+        let mut buffer = Vec::new();
+        let mut output = ColoredOutput::new(&mut buffer, WITH_FILENAME, red_matches());
+        output
+            .write_line(&line(Some("notes.txt"), "nothing here"), &[])
+            .unwrap();
+        assert_eq!(buffer, b"notes.txt:nothing here\n");
+    }
+
+    #[test]
+    fn test_context_and_separators() {
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, WITH_FILENAME);
+        // Nothing has been printed yet, so there's nothing to separate
+        output.write_separator().unwrap();
+        output
+            .write_context(&line(Some("a.log"), "before"))
+            .unwrap();
+        output
+            .write_line(&line(Some("a.log"), "match"), &[])
+            .unwrap();
+        output.write_separator().unwrap();
+        output.write_context(&line(None, "after")).unwrap();
+        assert_eq!(buffer, b"a.log-before\na.log:match\n--\nafter\n");
+    }
+
+    #[test]
+    fn test_binary_match() {
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, WITH_FILENAME);
+        output.binary_match(Some(Path::new("a.out"))).unwrap();
+        // A binary match counts as printed output when it comes to separators
+        output.write_separator().unwrap();
+        output.binary_match(None).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "Binary file a.out matches\n--\nBinary file (standard input) matches\n"
+        );
+
+        let mut buffered = BufferedOutput::default();
+        buffered.binary_match(Some(Path::new("lib.so"))).unwrap();
+        let mut buffer = Vec::new();
+        let mut output = ColoredOutput::new(&mut buffer, WITH_FILENAME, red_matches());
+        buffered.replay(&mut output).unwrap();
+        assert_eq!(buffer, b"Binary file lib.so matches\n");
+    }
+
+    #[test]
+    fn test_colored_context_is_not_highlighted() {
+        let mut buffer = Vec::new();
+        let mut output = ColoredOutput::new(&mut buffer, WITH_FILENAME, red_matches());
+        output.write_separator().unwrap();
+        output
+            .write_context(&line(Some("a.log"), "match nearby"))
+            .unwrap();
+        output.write_separator().unwrap();
+        assert_eq!(buffer, b"a.log-match nearby\n--\n");
+    }
+
+    #[test]
+    fn test_only_matching() {
+        let options = PrintOptions {
+            only_matching: true,
+            ..WITH_FILENAME
+        };
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, options);
+        output
+            .write_line(
+                &line(Some("a.txt"), "one fish two fish"),
+                &[4..8, 9..9, 13..17],
+            )
+            .unwrap();
+        assert_eq!(buffer, b"a.txt:fish\na.txt:fish\n");
+
+        let mut buffer = Vec::new();
+        let mut output = ColoredOutput::new(&mut buffer, options, red_matches());
+        output
+            .write_line(&line(None, "red fish"), &[0..3, 4..8])
+            .unwrap();
+        assert_eq!(buffer, b"\x1b[31mred\x1b[0m\n\x1b[31mfish\x1b[0m\n");
+    }
+
+    #[test]
+    fn test_line_number_column_and_offset() {
+        let options = PrintOptions {
+            line_number: true,
+            column: true,
+            byte_offset: true,
+            ..WITH_FILENAME
+        };
+        let matched = Line {
+            path: Some(Path::new("src/lib.rs")),
+            number: 12,
+            offset: 300,
+            content: b"let x = todo!();",
+        };
+        let context = Line {
+            number: 13,
+            offset: 317,
+            content: b"}",
+            ..matched
+        };
+
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, options);
+        output.write_line(&matched, &[8..12, 12..13]).unwrap();
+        output.write_context(&context).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "src/lib.rs:12:9:300:let x = todo!();\nsrc/lib.rs-13-317-}\n"
+        );
+
+        // Each match points at itself when printed on its own
+        let mut buffer = Vec::new();
+        let mut output = ColoredOutput::new(
+            &mut buffer,
+            PrintOptions {
+                only_matching: true,
+                ..options
+            },
+            red_matches(),
+        );
+        output.write_line(&matched, &[4..5, 8..12]).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "src/lib.rs:12:5:304:\x1b[31mx\x1b[0m\nsrc/lib.rs:12:9:308:\x1b[31mtodo\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_colored_output_with_default_theme() {
+        let options = PrintOptions {
+            line_number: true,
+            ..WITH_FILENAME
+        };
+        let mut buffer = Vec::new();
+        let mut output = ColoredOutput::new(&mut buffer, options, Theme::default());
+        output
+            .write_line(&line(Some("a.rs"), "let x"), &[4..5, 5..5])
+            .unwrap();
+        output.write_separator().unwrap();
+        output.write_context(&line(Some("a.rs"), "}")).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "\x1b[35ma.rs\x1b[0m\x1b[36m:\x1b[0m\x1b[32m1\x1b[0m\x1b[36m:\x1b[0mlet \x1b[01;31mx\x1b[0m\n\
+             \x1b[36m--\x1b[0m\n\
+             \x1b[35ma.rs\x1b[0m\x1b[36m-\x1b[0m\x1b[32m1\x1b[0m\x1b[36m-\x1b[0m}\n"
+        );
+    }
+
+    #[test]
+    fn test_colored_selected_and_context_lines() {
+        let theme = Theme {
+            selected_line: "1".to_string(),
+            context_line: "2".to_string(),
+            ..red_matches()
+        };
+        let mut buffer = Vec::new();
+        let mut output = ColoredOutput::new(&mut buffer, PrintOptions::default(), theme);
+        output
+            .write_line(&line(None, "a b c"), &[2..3, 4..5])
+            .unwrap();
+        output.write_context(&line(None, "near")).unwrap();
+        assert_eq!(
+            buffer,
+            b"\x1b[1ma \x1b[0m\x1b[31mb\x1b[0m\x1b[1m \x1b[0m\x1b[31mc\x1b[0m\n\x1b[2mnear\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_line_numbers_without_filename() {
+        let options = PrintOptions {
+            line_number: true,
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, options);
+        output
+            .write_line(&line(Some("a.txt"), "inverted"), &[])
+            .unwrap();
+        assert_eq!(buffer, b"1:inverted\n");
+    }
+
+    fn stats(matched_lines: u64) -> Stats {
+        Stats {
+            searches: 1,
+            searches_with_match: u64::from(matched_lines > 0),
+            matched_lines,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_count_output() {
+        let mut buffer = Vec::new();
+        let mut output = CountOutput::new(&mut buffer, WITH_FILENAME);
+        output.begin(Some(Path::new("a.txt"))).unwrap();
+        output.write_line(&line(Some("a.txt"), "hit"), &[]).unwrap();
+        output.end(Some(Path::new("a.txt")), &stats(1)).unwrap();
+        output.end(Some(Path::new("b.txt")), &stats(0)).unwrap();
+        assert_eq!(buffer, b"a.txt:1\nb.txt:0\n");
+
+        let mut buffer = Vec::new();
+        let mut output = CountOutput::new(&mut buffer, PrintOptions::default());
+        output.end(Some(Path::new("a.txt")), &stats(7)).unwrap();
+        assert_eq!(buffer, b"7\n");
+    }
+
+    #[test]
+    fn test_file_list_output() {
+        for (without_match, expected) in [(false, "a.txt\n(standard input)\n"), (true, "b.txt\n")] {
+            let mut buffer = Vec::new();
+            let mut output = FileListOutput::new(&mut buffer, without_match);
+            output.write_line(&line(Some("a.txt"), "hit"), &[]).unwrap();
+            output.end(Some(Path::new("a.txt")), &stats(1)).unwrap();
+            output.end(Some(Path::new("b.txt")), &stats(0)).unwrap();
+            output.end(None, &stats(2)).unwrap();
+            assert_eq!(String::from_utf8(buffer).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_tallying_output() {
+        let mut buffer = Vec::new();
+        let mut output = TallyingOutput::new(CountOutput::new(&mut buffer, WITH_FILENAME));
+        output.end(Some(Path::new("a.txt")), &stats(2)).unwrap();
+        output.end(Some(Path::new("b.txt")), &stats(0)).unwrap();
+        assert_eq!(output.totals().searches, 2);
+        assert_eq!(output.totals().searches_with_match, 1);
+        assert_eq!(output.totals().matched_lines, 2);
+        assert_eq!(buffer, b"a.txt:2\nb.txt:0\n");
+    }
+
+    #[test]
+    fn test_buffered_output_replays_in_order() {
+        let mut buffered = BufferedOutput::default();
+        buffered.begin(Some(Path::new("a"))).unwrap();
+        buffered.write_separator().unwrap();
+        buffered.write_context(&line(Some("a"), "one")).unwrap();
+        buffered
+            .write_line(&line(Some("a"), "two"), &[0..1, 2..3])
+            .unwrap();
+        buffered.write_separator().unwrap();
+        buffered.write_context(&line(None, "three")).unwrap();
+        buffered
+            .end(Some(Path::new("a")), &Stats::default())
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, WITH_FILENAME);
+        buffered.replay(&mut output).unwrap();
+        assert_eq!(buffer, b"a-one\na:two\n--\nthree\n");
+    }
+
+    #[test]
+    fn test_buffered_output_keeps_separator_state_across_replays() {
+        let mut first = BufferedOutput::default();
+        first.write_separator().unwrap();
+        first.write_line(&line(None, "first"), &[]).unwrap();
+        let mut second = BufferedOutput::default();
+        second.write_separator().unwrap();
+        second.write_line(&line(None, "second"), &[]).unwrap();
+
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, PrintOptions::default());
+        first.replay(&mut output).unwrap();
+        second.replay(&mut output).unwrap();
+        assert_eq!(buffer, b"first\n--\nsecond\n");
+    }
+}
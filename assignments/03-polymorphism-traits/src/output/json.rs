@@ -0,0 +1,294 @@
+use super::{Line, Sink};
+use crate::search::Stats;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+/// Prints results as JSON Lines for other programs to consume, following the shape of ripgrep's
+/// `--json` messages: a `begin` and `end` around each file with matches, a `match` or `context`
+/// message per line, and a `summary` at the very end. A binary file with matches gets a single
+/// `binary_match` message instead of its lines.
+pub struct JsonOutput<W: Write> {
+    writer: W,
+    /// Whether the `begin` message for the current file has been written. It's held back until
+    /// the first line, so files without matches don't produce any messages.
+    begun: bool,
+    total: Stats,
+    started: Instant,
+}
+
+impl<W: Write> JsonOutput<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            begun: false,
+            total: Stats::default(),
+            started: Instant::now(),
+        }
+    }
+
+    fn write_message(&mut self, kind: &str, data: Value) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &json!({ "type": kind, "data": data }))?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn write_begin_once(&mut self, path: Option<&Path>) -> io::Result<()> {
+        if self.begun {
+            return Ok(());
+        }
+        self.begun = true;
+        self.write_message("begin", json!({ "path": path_data(path) }))
+    }
+
+    fn write_line_message(
+        &mut self,
+        kind: &str,
+        line: &Line,
+        matches: &[Range<usize>],
+    ) -> io::Result<()> {
+        self.write_begin_once(line.path)?;
+        let submatches: Vec<_> = matches
+            .iter()
+            .map(|found| {
+                json!({
+                    "match": data(&line.content[found.clone()]),
+                    "start": found.start,
+                    "end": found.end,
+                })
+            })
+            .collect();
+        self.write_message(
+            kind,
+            json!({
+                "path": path_data(line.path),
+                "lines": data(line.content),
+                "line_number": line.number,
+                "absolute_offset": line.offset,
+                "submatches": submatches,
+            }),
+        )
+    }
+}
+
+impl<W: Write> Sink for JsonOutput<W> {
+    fn begin(&mut self, _path: Option<&Path>) -> io::Result<()> {
+        self.begun = false;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &Line, matches: &[Range<usize>]) -> io::Result<()> {
+        self.write_line_message("match", line, matches)
+    }
+
+    fn write_context(&mut self, line: &Line) -> io::Result<()> {
+        self.write_line_message("context", line, &[])
+    }
+
+    fn binary_match(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.write_begin_once(path)?;
+        self.write_message("binary_match", json!({ "path": path_data(path) }))
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        // Consumers can tell groups apart from the line numbers
+        Ok(())
+    }
+
+    fn end(&mut self, path: Option<&Path>, stats: &Stats) -> io::Result<()> {
+        self.total.add(stats);
+        if !self.begun {
+            return Ok(());
+        }
+        self.begun = false;
+        self.write_message(
+            "end",
+            json!({ "path": path_data(path), "stats": stats_data(stats) }),
+        )
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let elapsed = self.started.elapsed();
+        let summary = json!({
+            "elapsed_total": {
+                "secs": elapsed.as_secs(),
+                "nanos": elapsed.subsec_nanos(),
+                "human": format!("{:.6}s", elapsed.as_secs_f64()),
+            },
+            "stats": stats_data(&self.total),
+        });
+        self.write_message("summary", summary)?;
+        self.writer.flush()
+    }
+
+    fn counts_lines(&self) -> bool {
+        // The stats in the end and summary messages include it
+        true
+    }
+}
+
+/// Text is written as `{"text": ...}` when it's valid UTF-8, and as base64 in `{"bytes": ...}`
+/// otherwise, since JSON strings can't hold arbitrary bytes.
+fn data(bytes: &[u8]) -> Value {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => json!({ "text": text }),
+        Err(_) => json!({ "bytes": BASE64_STANDARD.encode(bytes) }),
+    }
+}
+
+fn path_data(path: Option<&Path>) -> Value {
+    path.map_or(Value::Null, |path| {
+        data(path.as_os_str().as_encoded_bytes())
+    })
+}
+
+fn stats_data(stats: &Stats) -> Value {
+    json!({
+        "searches": stats.searches,
+        "searches_with_match": stats.searches_with_match,
+        "bytes_searched": stats.bytes_searched,
+        "matched_lines": stats.matched_lines,
+        "matches": stats.matches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(buffer: &[u8]) -> Vec<Value> {
+        buffer
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect()
+    }
+
+    fn stats(matched_lines: u64, matches: u64) -> Stats {
+        Stats {
+            searches: 1,
+            searches_with_match: u64::from(matched_lines > 0),
+            bytes_searched: 100,
+            matched_lines,
+            matches,
+        }
+    }
+
+    #[test]
+    fn test_json_binary_match() {
+        let path = Path::new("a.out");
+        let mut buffer = Vec::new();
+        let mut output = JsonOutput::new(&mut buffer);
+        output.begin(Some(path)).unwrap();
+        output.binary_match(Some(path)).unwrap();
+        output.end(Some(path), &stats(2, 2)).unwrap();
+        output.finish().unwrap();
+
+        let messages = messages(&buffer);
+        let kinds: Vec<_> = messages.iter().map(|message| &message["type"]).collect();
+        assert_eq!(kinds, ["begin", "binary_match", "end", "summary"]);
+        assert_eq!(messages[1]["data"], json!({ "path": { "text": "a.out" } }));
+    }
+
+    #[test]
+    fn test_json_output() {
+        let path = Path::new("logs/app.log");
+        let mut buffer = Vec::new();
+        let mut output = JsonOutput::new(&mut buffer);
+        output.begin(Some(path)).unwrap();
+        let context = Line {
+            path: Some(path),
+            number: 1,
+            offset: 0,
+            content: b"starting up",
+        };
+        output.write_context(&context).unwrap();
+        let matched = Line {
+            path: Some(path),
+            number: 2,
+            offset: 12,
+            content: b"error: disk error",
+        };
+        output.write_line(&matched, &[0..5, 12..17]).unwrap();
+        output.end(Some(path), &stats(1, 2)).unwrap();
+        output.finish().unwrap();
+
+        let messages = messages(&buffer);
+        let kinds: Vec<_> = messages.iter().map(|message| &message["type"]).collect();
+        assert_eq!(kinds, ["begin", "context", "match", "end", "summary"]);
+        assert_eq!(
+            messages[0]["data"],
+            json!({ "path": { "text": "logs/app.log" } })
+        );
+        assert_eq!(
+            messages[2]["data"],
+            json!({
+                "path": { "text": "logs/app.log" },
+                "lines": { "text": "error: disk error" },
+                "line_number": 2,
+                "absolute_offset": 12,
+                "submatches": [
+                    { "match": { "text": "error" }, "start": 0, "end": 5 },
+                    { "match": { "text": "error" }, "start": 12, "end": 17 },
+                ],
+            })
+        );
+        assert_eq!(messages[1]["data"]["submatches"], json!([]));
+        assert_eq!(messages[3]["data"]["stats"]["matches"], 2);
+        assert!(messages[4]["data"]["elapsed_total"]["secs"].is_u64());
+    }
+
+    #[test]
+    fn test_json_output_skips_files_without_matches() {
+        let mut buffer = Vec::new();
+        let mut output = JsonOutput::new(&mut buffer);
+        for (name, found) in [("a.txt", 0), ("b.txt", 3)] {
+            let path = Path::new(name);
+            output.begin(Some(path)).unwrap();
+            for number in 1..=found {
+                let line = Line {
+                    path: Some(path),
+                    number,
+                    offset: 0,
+                    content: b"hit",
+                };
+                output.write_line(&line, &[]).unwrap();
+            }
+            output.end(Some(path), &stats(found, found)).unwrap();
+        }
+        output.finish().unwrap();
+
+        let messages = messages(&buffer);
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0]["data"]["path"]["text"], "b.txt");
+        assert_eq!(
+            messages[5]["data"]["stats"],
+            json!({
+                "searches": 2,
+                "searches_with_match": 1,
+                "bytes_searched": 200,
+                "matched_lines": 3,
+                "matches": 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_output_encodes_invalid_utf8_as_base64() {
+        let mut buffer = Vec::new();
+        let mut output = JsonOutput::new(&mut buffer);
+        let line = Line {
+            path: None,
+            number: 1,
+            offset: 0,
+            content: b"caf\xe9",
+        };
+        output.write_line(&line, &[]).unwrap();
+
+        let messages = messages(&buffer);
+        assert_eq!(messages[0]["data"]["path"], Value::Null);
+        assert_eq!(messages[1]["data"]["lines"], json!({ "bytes": "Y2Fm6Q==" }));
+    }
+}
@@ -0,0 +1,221 @@
+use colored::Color;
+use std::env;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// When to color output, as chosen with `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorChoice {
+    /// Color output only when it's going straight to a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+    /// Always color, but only matches, in this color. `--color` used to only take a color, so
+    /// this keeps `--color red` working as it did.
+    Matches(Color),
+}
+
+impl ColorChoice {
+    /// Whether to color output, given whether stdout is a terminal.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => is_terminal,
+            ColorChoice::Always | ColorChoice::Matches(_) => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(choice: &str) -> Result<Self, Self::Err> {
+        match choice {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Color::from_str(choice)
+                .map(ColorChoice::Matches)
+                .map_err(|()| {
+                    format!("expected auto, always, never or a color name, not {choice:?}")
+                }),
+        }
+    }
+}
+
+/// The colors `ColoredOutput` uses for each part of a line, as SGR parameter strings like
+/// `01;31`. An empty string leaves that part uncolored.
+///
+/// Themes are written the same way as GNU grep's `GREP_COLORS`, as colon separated `key=value`
+/// pairs like `ms=01;32:fn=34:se=2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    /// `ms`, or `mt`: matches within selected lines.
+    pub selected_match: String,
+    /// `sl`: the rest of each selected line.
+    pub selected_line: String,
+    /// `cx`: context lines.
+    pub context_line: String,
+    /// `fn`: file paths.
+    pub path: String,
+    /// `ln`: line numbers, and columns.
+    pub line_number: String,
+    /// `bn`: byte offsets.
+    pub byte_offset: String,
+    /// `se`: the `:` and `-` after each prefix, and the `--` between groups of context.
+    pub separator: String,
+}
+
+impl Theme {
+    /// A theme that colors nothing.
+    pub const NONE: Theme = Theme {
+        selected_match: String::new(),
+        selected_line: String::new(),
+        context_line: String::new(),
+        path: String::new(),
+        line_number: String::new(),
+        byte_offset: String::new(),
+        separator: String::new(),
+    };
+
+    /// A theme that only colors matches, in `color`.
+    pub fn matches_only(color: Color) -> Self {
+        Self {
+            selected_match: color.to_fg_str().to_string(),
+            ..Self::NONE
+        }
+    }
+
+    /// The default theme, changed by `GREP_COLORS` and then by `GREPRS_COLORS` if they're set.
+    pub fn from_env() -> Self {
+        let mut theme = Self::default();
+        for variable in ["GREP_COLORS", "GREPRS_COLORS"] {
+            if let Ok(spec) = env::var(variable) {
+                theme.apply(&spec);
+            }
+        }
+        theme
+    }
+
+    /// Sets the colors given in `spec`, in `GREP_COLORS` format. Like grep, capabilities this
+    /// theme has no use for (`mc`, `rv`, `ne`) and malformed entries are skipped rather than
+    /// treated as errors, since the same variable is shared with other programs.
+    pub fn apply(&mut self, spec: &str) {
+        for entry in spec.split(':') {
+            let Some((key, style)) = entry.split_once('=') else {
+                continue;
+            };
+            if !style
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || byte == b';')
+            {
+                continue;
+            }
+            let field = match key {
+                "mt" | "ms" => &mut self.selected_match,
+                "sl" => &mut self.selected_line,
+                "cx" => &mut self.context_line,
+                "fn" => &mut self.path,
+                "ln" => &mut self.line_number,
+                "bn" => &mut self.byte_offset,
+                "se" => &mut self.separator,
+                _ => continue,
+            };
+            *field = style.to_string();
+        }
+    }
+}
+
+impl Default for Theme {
+    /// GNU grep's colors: bold red matches, magenta paths, green numbers and cyan separators.
+    fn default() -> Self {
+        Self {
+            selected_match: "01;31".to_string(),
+            path: "35".to_string(),
+            line_number: "32".to_string(),
+            byte_offset: "32".to_string(),
+            separator: "36".to_string(),
+            ..Self::NONE
+        }
+    }
+}
+
+/// Writes `text` in `style`, wrapped in the escape sequences to turn it on and back off.
+pub(super) fn paint(writer: &mut impl Write, style: &str, text: &[u8]) -> io::Result<()> {
+    if style.is_empty() || text.is_empty() {
+        return writer.write_all(text);
+    }
+    write!(writer, "\x1b[{style}m")?;
+    writer.write_all(text)?;
+    writer.write_all(b"\x1b[0m")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_grep_colors() {
+        let mut theme = Theme::default();
+        theme.apply("ms=01;32:fn=34:ln=:se=2:mc=01;33:ne");
+        assert_eq!(theme.selected_match, "01;32");
+        assert_eq!(theme.path, "34");
+        assert_eq!(theme.line_number, "");
+        assert_eq!(theme.separator, "2");
+        // Untouched entries keep their defaults
+        assert_eq!(theme.byte_offset, "32");
+
+        theme.apply("mt=7");
+        assert_eq!(theme.selected_match, "7");
+    }
+
+    #[test]
+    fn test_apply_skips_malformed_entries() {
+        let mut theme = Theme::default();
+        theme.apply("ms=red:fn:=1:zz=1:cx=2");
+        assert_eq!(
+            theme,
+            Theme {
+                context_line: "2".to_string(),
+                ..Theme::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_matches_only() {
+        let theme = Theme::matches_only(Color::Red);
+        assert_eq!(theme.selected_match, "31");
+        assert_eq!(
+            theme,
+            Theme {
+                selected_match: "31".to_string(),
+                ..Theme::NONE
+            }
+        );
+    }
+
+    #[test]
+    fn test_paint() {
+        let mut buffer = Vec::new();
+        paint(&mut buffer, "01;31", b"hit").unwrap();
+        paint(&mut buffer, "", b" plain").unwrap();
+        paint(&mut buffer, "32", b"").unwrap();
+        assert_eq!(buffer, b"\x1b[01;31mhit\x1b[0m plain");
+    }
+
+    #[test]
+    fn test_color_choice() {
+        assert_eq!("auto".parse(), Ok(ColorChoice::Auto));
+        assert_eq!("never".parse(), Ok(ColorChoice::Never));
+        assert_eq!("blue".parse(), Ok(ColorChoice::Matches(Color::Blue)));
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+
+        assert!(ColorChoice::Auto.enabled(true));
+        assert!(!ColorChoice::Auto.enabled(false));
+        assert!(ColorChoice::Always.enabled(false));
+        assert!(ColorChoice::Matches(Color::Red).enabled(false));
+        assert!(!ColorChoice::Never.enabled(true));
+    }
+}
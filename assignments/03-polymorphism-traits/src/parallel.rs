@@ -0,0 +1,175 @@
+use crate::output::{BufferedOutput, Sink};
+use crate::walk::WalkedFile;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Searches `files` on a pool of `threads` workers using `search`, while printing each file's
+/// results to `output` in the same order the files came in.
+///
+/// Each worker buffers the results for one file at a time. The calling thread holds finished
+/// files back until every file before them has been printed, so output never interleaves and is
+/// the same from run to run. Errors for individual files are reported in order too, without
+/// stopping the search. Returns how many files couldn't be searched.
+pub fn search_in_order<I, F>(
+    files: I,
+    threads: usize,
+    output: &mut dyn Sink,
+    search: F,
+) -> Result<usize>
+where
+    I: Iterator<Item = Result<WalkedFile>> + Send,
+    F: Fn(&WalkedFile, &mut dyn Sink) -> Result<()> + Sync,
+{
+    let counts_lines = output.counts_lines();
+    thread::scope(|scope| {
+        // Bounded so the walk doesn't run too far ahead of the workers
+        let (job_sender, job_receiver) = mpsc::sync_channel::<(usize, WalkedFile)>(threads * 4);
+        let (result_sender, result_receiver) = mpsc::channel::<(usize, Result<BufferedOutput>)>();
+        // Shared by the workers only, so it's dropped once they've all stopped. That way the walk
+        // can't block forever on a full queue if printing fails.
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let walk_errors = result_sender.clone();
+        scope.spawn(move || {
+            for (index, file) in files.enumerate() {
+                let sent = match file {
+                    Ok(file) => job_sender.send((index, file)).is_ok(),
+                    Err(error) => walk_errors.send((index, Err(error))).is_ok(),
+                };
+                if !sent {
+                    break;
+                }
+            }
+        });
+
+        for _ in 0..threads {
+            let job_receiver = Arc::clone(&job_receiver);
+            let result_sender = result_sender.clone();
+            let search = &search;
+            scope.spawn(move || loop {
+                // The lock is released as soon as a job has been taken
+                let job = job_receiver.lock().unwrap().recv();
+                let Ok((index, file)) = job else {
+                    break;
+                };
+                let mut buffered = BufferedOutput::new(counts_lines);
+                let result = search(&file, &mut buffered).map(|()| buffered);
+                if result_sender.send((index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(job_receiver);
+        drop(result_sender);
+
+        let mut finished = BTreeMap::new();
+        let mut next = 0;
+        let mut errors = 0;
+        for (index, result) in result_receiver {
+            finished.insert(index, result);
+            while let Some(result) = finished.remove(&next) {
+                next += 1;
+                match result {
+                    Ok(buffered) => buffered.replay(output)?,
+                    // One unreadable file shouldn't stop the rest of the search
+                    Err(error) => {
+                        eprintln!("greprs: {error:#}");
+                        errors += 1;
+                    }
+                }
+            }
+        }
+        Ok(errors)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{Line, PlainOutput, PrintOptions};
+    use anyhow::bail;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    const WITH_FILENAME: PrintOptions = PrintOptions {
+        with_filename: true,
+        only_matching: false,
+        line_number: false,
+        column: false,
+        byte_offset: false,
+    };
+
+    fn files(count: usize) -> Vec<Result<WalkedFile>> {
+        (0..count)
+            .map(|index| {
+                Ok(WalkedFile {
+                    path: PathBuf::from(format!("file{index}")),
+                    explicit: false,
+                })
+            })
+            .collect()
+    }
+
+    /// Pretends to search a file, taking less time for later files so they tend to finish first.
+    fn fake_search(file: &WalkedFile, output: &mut dyn Sink) -> Result<()> {
+        let index: u64 = file.path.to_str().unwrap()[4..].parse().unwrap();
+        thread::sleep(Duration::from_millis(20u64.saturating_sub(index)));
+        for (number, content) in [(1, "first"), (2, "second")] {
+            let line = Line {
+                path: Some(&file.path),
+                number,
+                offset: 0,
+                content: content.as_bytes(),
+            };
+            output.write_line(&line, &[])?;
+        }
+        Ok(())
+    }
+
+    fn expected(count: usize) -> String {
+        (0..count)
+            .map(|index| format!("file{index}:first\nfile{index}:second\n"))
+            .collect()
+    }
+
+    #[test]
+    fn test_search_in_order() {
+        for threads in [1, 2, 8] {
+            let mut buffer = Vec::new();
+            let mut output = PlainOutput::new(&mut buffer, WITH_FILENAME);
+            search_in_order(files(20).into_iter(), threads, &mut output, fake_search).unwrap();
+            assert_eq!(String::from_utf8(buffer).unwrap(), expected(20));
+        }
+    }
+
+    #[test]
+    fn test_search_in_order_keeps_going_after_errors() {
+        let mut inputs = files(5);
+        inputs.insert(2, Err(anyhow::anyhow!("permission denied")));
+
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, WITH_FILENAME);
+        let errors = search_in_order(inputs.into_iter(), 4, &mut output, |file, output| {
+            if file.path == Path::new("file3") {
+                bail!("failed to open file3");
+            }
+            fake_search(file, output)
+        })
+        .unwrap();
+        assert_eq!(errors, 2);
+
+        let expected = expected(5).replace("file3:first\nfile3:second\n", "");
+        assert_eq!(String::from_utf8(buffer).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_search_in_order_with_no_files() {
+        let mut buffer = Vec::new();
+        let mut output = PlainOutput::new(&mut buffer, WITH_FILENAME);
+        let errors = search_in_order(std::iter::empty(), 4, &mut output, fake_search).unwrap();
+        assert_eq!(errors, 0);
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::matcher::Matcher;
+use crate::search::trim_line_terminator;
+use anyhow::{Context, Result};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A `--replace` template, parsed once up front so it can be filled in for every match.
+///
+/// `$1` or `${1}` refers to a capture group by number, `$name` or `${name}` by name, and `$0` to
+/// the whole match. `$$` is a literal `$`. Like the `regex` crate, `$name` takes as many word
+/// characters as it can, so use braces to put text right after a reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Literal(Vec<u8>),
+    Group(Group),
+}
+
+/// A reference to a capture group in a template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Group {
+    Index(usize),
+    Name(String),
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Self {
+        let mut pieces = Vec::new();
+        let mut literal = Vec::new();
+        let mut rest = template;
+        while let Some(dollar) = rest.find('$') {
+            literal.extend_from_slice(&rest.as_bytes()[..dollar]);
+            let after = &rest[dollar + 1..];
+            if let Some(after) = after.strip_prefix('$') {
+                literal.push(b'$');
+                rest = after;
+                continue;
+            }
+            let (name, remaining) = match after.strip_prefix('{') {
+                Some(braced) => match braced.find('}') {
+                    Some(close) => (&braced[..close], &braced[close + 1..]),
+                    // An unclosed brace isn't a reference, so keep it as written
+                    None => ("", after),
+                },
+                None => {
+                    let end = after
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(after.len());
+                    after.split_at(end)
+                }
+            };
+            if name.is_empty() {
+                literal.push(b'$');
+                rest = after;
+                continue;
+            }
+            if !literal.is_empty() {
+                pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+            }
+            let group = match name.parse() {
+                Ok(index) => Group::Index(index),
+                Err(_) => Group::Name(name.to_string()),
+            };
+            pieces.push(Piece::Group(group));
+            rest = remaining;
+        }
+        literal.extend_from_slice(rest.as_bytes());
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Self { pieces }
+    }
+
+    /// Appends the template to `dst`, looking up each group reference with `group`. Groups that
+    /// don't exist or didn't take part in the match are left empty.
+    pub fn expand<'h>(&self, dst: &mut Vec<u8>, group: impl Fn(&Group) -> Option<&'h [u8]>) {
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(literal) => dst.extend_from_slice(literal),
+                Piece::Group(reference) => dst.extend_from_slice(group(reference).unwrap_or(b"")),
+            }
+        }
+    }
+}
+
+/// Appends `line` to `dst` with every one of `matches` swapped for the expanded template.
+/// Returns where each replacement ended up in `dst`, so they can be highlighted.
+pub fn replace_matches(
+    matcher: &dyn Matcher,
+    line: &[u8],
+    matches: &[Range<usize>],
+    template: &Template,
+    dst: &mut Vec<u8>,
+) -> Vec<Range<usize>> {
+    let mut replaced = Vec::with_capacity(matches.len());
+    let mut copied = 0;
+    for found in matches {
+        dst.extend_from_slice(&line[copied..found.start]);
+        let start = dst.len();
+        matcher.expand(line, found.clone(), template, dst);
+        replaced.push(start..dst.len());
+        copied = found.end;
+    }
+    dst.extend_from_slice(&line[copied..]);
+    replaced
+}
+
+/// Rewrites the file at `path` with every match replaced, returning how many lines changed.
+///
+/// The new contents are written to a temporary file next to the original, which is then renamed
+/// over it, so the file is never left half written. If `backup_suffix` is set, the original is
+/// first copied to a file with that suffix added to its name. Files without any matches are
+/// left alone. A symlink is followed, so it's the file it points to that gets rewritten, and
+/// backed up.
+pub fn rewrite_file(
+    path: &Path,
+    matcher: &dyn Matcher,
+    template: &Template,
+    backup_suffix: Option<&str>,
+) -> Result<u64> {
+    // Renaming over a symlink would replace the link rather than the file it points to
+    let path =
+        &fs::canonicalize(path).with_context(|| format!("failed to resolve {}", path.display()))?;
+    let contents = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut rewritten = Vec::with_capacity(contents.len());
+    let mut changed = 0;
+    for line in contents.split_inclusive(|&byte| byte == b'\n') {
+        let content = trim_line_terminator(line);
+        let matches = matcher.find_all(content);
+        if matches.is_empty() {
+            rewritten.extend_from_slice(line);
+            continue;
+        }
+        replace_matches(matcher, content, &matches, template, &mut rewritten);
+        rewritten.extend_from_slice(&line[content.len()..]);
+        changed += 1;
+    }
+    if changed == 0 {
+        return Ok(0);
+    }
+
+    if let Some(suffix) = backup_suffix {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(suffix);
+        let backup = PathBuf::from(backup);
+        fs::copy(path, &backup).with_context(|| {
+            format!(
+                "failed to back up {} to {}",
+                path.display(),
+                backup.display()
+            )
+        })?;
+    }
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut temporary = tempfile::NamedTempFile::new_in(directory).with_context(|| {
+        format!(
+            "failed to create a temporary file in {}",
+            directory.display()
+        )
+    })?;
+    std::io::Write::write_all(&mut temporary, &rewritten)?;
+    fs::set_permissions(temporary.path(), fs::metadata(path)?.permissions())?;
+    temporary
+        .persist(path)
+        .with_context(|| format!("failed to replace {}", path.display()))?;
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{LiteralMatcher, RegexMatcher};
+
+    fn expand(template: &str, groups: &[(&str, &str)]) -> String {
+        let mut dst = Vec::new();
+        Template::parse(template).expand(&mut dst, |group| {
+            let key = match group {
+                Group::Index(index) => index.to_string(),
+                Group::Name(name) => name.clone(),
+            };
+            groups
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.as_bytes())
+        });
+        String::from_utf8(dst).unwrap()
+    }
+
+    #[test]
+    fn test_template_references() {
+        let groups = [("0", "whole"), ("1", "one"), ("year", "2024")];
+        assert_eq!(expand("[$1]", &groups), "[one]");
+        assert_eq!(expand("${1}st $0", &groups), "onest whole");
+        assert_eq!(expand("$year-${year}", &groups), "2024-2024");
+        // $1st is a (missing) group named "1st", just like in the regex crate
+        assert_eq!(expand("$1st", &groups), "");
+        assert_eq!(expand("$missing!", &groups), "!");
+    }
+
+    #[test]
+    fn test_template_literal_dollars() {
+        assert_eq!(expand("cost: $$5", &[]), "cost: $5");
+        assert_eq!(expand("a $ b", &[]), "a $ b");
+        assert_eq!(expand("end$", &[]), "end$");
+        assert_eq!(expand("${unclosed", &[]), "${unclosed");
+    }
+
+    #[test]
+    fn test_replace_matches_with_regex_groups() {
+        let matcher = RegexMatcher::new(r"(?P<key>\w+)=(\d+)", false).unwrap();
+        let line = b"a=1, bb=22";
+        let mut dst = Vec::new();
+        let replaced = replace_matches(
+            &matcher,
+            line,
+            &matcher.find_all(line),
+            &Template::parse("$2:${key}"),
+            &mut dst,
+        );
+        assert_eq!(dst, b"1:a, 22:bb");
+        assert_eq!(replaced, vec![0..3, 5..10]);
+    }
+
+    #[test]
+    fn test_replace_matches_with_literal() {
+        let matcher = LiteralMatcher::new("cat", false).unwrap();
+        let line = b"cat scatter";
+        let mut dst = Vec::new();
+        replace_matches(
+            &matcher,
+            line,
+            &matcher.find_all(line),
+            &Template::parse("<$0$1>"),
+            &mut dst,
+        );
+        assert_eq!(dst, b"<cat> s<cat>ter");
+    }
+
+    #[test]
+    fn test_rewrite_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.ini");
+        fs::write(&path, "port=80\r\nhost=example.com\nport=8080").unwrap();
+
+        let matcher = RegexMatcher::new(r"port=(\d+)", false).unwrap();
+        let changed = rewrite_file(&path, &matcher, &Template::parse("port=1$1"), None).unwrap();
+        assert_eq!(changed, 2);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "port=180\r\nhost=example.com\nport=18080"
+        );
+        // Nothing but the file itself should be left in the directory
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_rewrite_file_with_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "colour\n").unwrap();
+
+        let matcher = LiteralMatcher::new("colour", false).unwrap();
+        rewrite_file(&path, &matcher, &Template::parse("color"), Some(".bak")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "color\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("notes.txt.bak")).unwrap(),
+            "colour\n"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_file_without_matches_is_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "nothing to see\n").unwrap();
+
+        let matcher = LiteralMatcher::new("absent", false).unwrap();
+        let changed = rewrite_file(&path, &matcher, &Template::parse("x"), Some(".bak")).unwrap();
+        assert_eq!(changed, 0);
+        assert!(!dir.path().join("notes.txt.bak").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rewrite_file_through_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&real, "colour\n").unwrap();
+        std::os::unix::fs::symlink("real.txt", &link).unwrap();
+
+        let matcher = LiteralMatcher::new("colour", false).unwrap();
+        rewrite_file(&link, &matcher, &Template::parse("color"), Some(".bak")).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "color\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("real.txt.bak")).unwrap(),
+            "colour\n"
+        );
+    }
+}
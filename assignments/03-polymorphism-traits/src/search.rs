@@ -0,0 +1,1113 @@
+use crate::context::ContextWindow;
+use crate::input::{self, Source};
+use crate::matcher::Matcher;
+use crate::output::{Line, Sink};
+use crate::replace::{self, Template};
+use anyhow::Result;
+use std::io::{self, BufRead};
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Runs a matcher over every line of an input and hands the selected lines to a sink.
+#[derive(Debug, Default, Clone)]
+pub struct Searcher {
+    pub invert_match: bool,
+    /// Lines of context to print before each selected line.
+    pub before_context: usize,
+    /// Lines of context to print after each selected line.
+    pub after_context: usize,
+    /// Run the matcher over whole inputs instead of line by line, so matches can span lines.
+    pub multiline: bool,
+    /// Stop searching an input once this many lines have been selected and their trailing
+    /// context printed.
+    pub max_count: Option<u64>,
+    /// Print selected lines with every match swapped for this template.
+    pub replace: Option<Template>,
+    /// What to do with inputs that look binary.
+    pub binary_files: BinaryFiles,
+    /// Leave the first line of each input out of the search, like the header of a CSV file. It
+    /// still counts towards line numbers and offsets.
+    pub skip_header: bool,
+}
+
+/// How to search an input with a NUL byte in its first block, which is taken to mean it's binary.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFiles {
+    /// Search it, but instead of its lines, tell the sink once that it matched.
+    #[default]
+    Binary,
+    /// Search it like any other input.
+    Text,
+    /// Don't search it, and treat it as having no matches.
+    WithoutMatch,
+}
+
+impl FromStr for BinaryFiles {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "binary" => Ok(BinaryFiles::Binary),
+            "text" => Ok(BinaryFiles::Text),
+            "without-match" => Ok(BinaryFiles::WithoutMatch),
+            _ => Err(format!(
+                "expected binary, text or without-match, not {policy:?}"
+            )),
+        }
+    }
+}
+
+/// What a search found, either in one input or added up over several.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// How many inputs were searched.
+    pub searches: u64,
+    /// How many inputs had at least one selected line.
+    pub searches_with_match: u64,
+    pub bytes_searched: u64,
+    /// How many lines were selected, which are the non-matching ones with `invert_match`.
+    pub matched_lines: u64,
+    /// How many times the needle was found within the selected lines.
+    pub matches: u64,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.bytes_searched += other.bytes_searched;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+}
+
+impl Searcher {
+    /// Searches everything `source` holds, memory mapping it if it can be and streaming it if
+    /// not.
+    pub fn search_source(
+        &self,
+        matcher: &dyn Matcher,
+        source: &dyn Source,
+        output: &mut dyn Sink,
+    ) -> Result<Stats> {
+        if let Some(map) = source.map()? {
+            return self.search_slice(matcher, &map, source.path(), output);
+        }
+        let mut reader = source.open()?;
+        self.search_reader(matcher, &mut *reader, source.path(), output)
+    }
+
+    /// Searches `reader` line by line. This is the fallback for inputs that can't be memory
+    /// mapped, like stdin and pipes.
+    pub fn search_reader(
+        &self,
+        matcher: &dyn Matcher,
+        reader: &mut dyn BufRead,
+        path: Option<&Path>,
+        output: &mut dyn Sink,
+    ) -> Result<Stats> {
+        if self.binary_files != BinaryFiles::Text && input::looks_binary(reader.fill_buf()?) {
+            return self.search_binary(path, output, |searcher, output| {
+                searcher.search_text_reader(matcher, reader, path, output)
+            });
+        }
+        self.search_text_reader(matcher, reader, path, output)
+    }
+
+    /// Searches a whole input that's already in memory. Lines are borrowed straight out of
+    /// `haystack`, so nothing is copied.
+    pub fn search_slice(
+        &self,
+        matcher: &dyn Matcher,
+        haystack: &[u8],
+        path: Option<&Path>,
+        output: &mut dyn Sink,
+    ) -> Result<Stats> {
+        if self.binary_files != BinaryFiles::Text && input::looks_binary(haystack) {
+            return self.search_binary(path, output, |searcher, output| {
+                searcher.search_text_slice(matcher, haystack, path, output)
+            });
+        }
+        self.search_text_slice(matcher, haystack, path, output)
+    }
+
+    /// Applies the binary file policy to a binary input, which `search` searches as text with the
+    /// searcher it's given.
+    fn search_binary(
+        &self,
+        path: Option<&Path>,
+        output: &mut dyn Sink,
+        search: impl FnOnce(&Searcher, &mut dyn Sink) -> Result<Stats>,
+    ) -> Result<Stats> {
+        match self.binary_files {
+            BinaryFiles::Text => search(self, output),
+            BinaryFiles::Binary => {
+                // Like grep, stop at the first selected line, since that's all that gets
+                // reported, unless the sink needs to know how many lines there were
+                let first_only;
+                let searcher = if output.counts_lines() {
+                    self
+                } else {
+                    first_only = Searcher {
+                        max_count: Some(self.max_count.map_or(1, |max| max.min(1))),
+                        before_context: 0,
+                        after_context: 0,
+                        ..self.clone()
+                    };
+                    &first_only
+                };
+                search(
+                    searcher,
+                    &mut BinaryMatches {
+                        output,
+                        path,
+                        reported: false,
+                    },
+                )
+            }
+            BinaryFiles::WithoutMatch => {
+                let stats = Stats {
+                    searches: 1,
+                    ..Default::default()
+                };
+                output.begin(path)?;
+                output.end(path, &stats)?;
+                Ok(stats)
+            }
+        }
+    }
+
+    fn search_text_reader(
+        &self,
+        matcher: &dyn Matcher,
+        reader: &mut dyn BufRead,
+        path: Option<&Path>,
+        output: &mut dyn Sink,
+    ) -> Result<Stats> {
+        if self.multiline {
+            let mut haystack = Vec::new();
+            reader.read_to_end(&mut haystack)?;
+            return self.search_text_slice(matcher, &haystack, path, output);
+        }
+        let mut lines = LineSelector::new(self, matcher, path, output)?;
+        // Reuse one buffer for every line instead of allocating a String per line
+        let mut line = Vec::new();
+        if self.skip_header {
+            reader.read_until(b'\n', &mut line)?;
+            lines.skip(&line);
+            line.clear();
+        }
+        while !lines.is_done() && reader.read_until(b'\n', &mut line)? > 0 {
+            lines.next_line(&line)?;
+            line.clear();
+        }
+        lines.finish()
+    }
+
+    fn search_text_slice(
+        &self,
+        matcher: &dyn Matcher,
+        haystack: &[u8],
+        path: Option<&Path>,
+        output: &mut dyn Sink,
+    ) -> Result<Stats> {
+        let mut lines = LineSelector::new(self, matcher, path, output)?;
+        let haystack = if self.skip_header {
+            let header_len =
+                memchr::memchr(b'\n', haystack).map_or(haystack.len(), |newline| newline + 1);
+            lines.skip(&haystack[..header_len]);
+            &haystack[header_len..]
+        } else {
+            haystack
+        };
+        if self.multiline {
+            return self.search_multiline(matcher, haystack, lines);
+        }
+        if self.invert_match || self.before_context > 0 || self.after_context > 0 {
+            // Every line matters here, either because it's selected or might be context
+            return search_lines(haystack, lines);
+        }
+        if matcher.searches_lines() {
+            return search_lines(haystack, lines);
+        }
+
+        // Otherwise let the matcher scan the whole buffer at once, and only go looking for line
+        // boundaries around the places it finds something
+        let mut position = 0;
+        while !lines.is_done() {
+            let Some(found) = matcher.find_at(haystack, position) else {
+                break;
+            };
+            let start = memchr::memrchr(b'\n', &haystack[position..found.start])
+                .map_or(position, |newline| position + newline + 1);
+            if start == haystack.len() {
+                // An empty match after the final line terminator isn't on any line
+                break;
+            }
+            let end = memchr::memchr(b'\n', &haystack[found.start..])
+                .map_or(haystack.len(), |newline| found.start + newline + 1);
+            lines.skip(&haystack[position..start]);
+            // The match might have run across a line boundary, so check the line on its own
+            lines.next_line(&haystack[start..end])?;
+            position = end;
+            if position == haystack.len() {
+                break;
+            }
+            // A matcher that runs across lines can keep finding matches that run a long way
+            // past the line they start on, each one scanned again from the next line. Going line
+            // by line from here keeps that from taking quadratic time.
+            if found.end > end {
+                return search_lines(&haystack[position..], lines);
+            }
+        }
+        if !lines.is_done() {
+            lines.skip(&haystack[position..]);
+        }
+        lines.finish()
+    }
+
+    /// Finds every match in the whole of `haystack` up front, then selects each line a match
+    /// touches, with the part of the match that falls on it. A match spanning several lines is
+    /// printed as a block of consecutive lines.
+    fn search_multiline(
+        &self,
+        matcher: &dyn Matcher,
+        haystack: &[u8],
+        mut lines: LineSelector,
+    ) -> Result<Stats> {
+        let matches = matcher.find_all(haystack);
+        let mut next = 0;
+        let mut line_start = 0;
+        for line in haystack.split_inclusive(|&byte| byte == b'\n') {
+            if lines.is_done() {
+                break;
+            }
+            let line_end = line_start + line.len();
+            let content_len = trim_line_terminator(line).len();
+            // Matches are in order, so the ones that finished on earlier lines are done with
+            while matches.get(next).is_some_and(|found| {
+                let last = if found.is_empty() {
+                    found.start
+                } else {
+                    found.end - 1
+                };
+                last < line_start
+            }) {
+                next += 1;
+            }
+            let mut ranges = Vec::new();
+            let mut starting_here = 0;
+            for found in &matches[next..] {
+                let on_line =
+                    found.start < line_end || (found.start == line_end && !line.ends_with(b"\n"));
+                if !on_line {
+                    break;
+                }
+                let start = (found.start.max(line_start) - line_start).min(content_len);
+                let end = found
+                    .end
+                    .min(line_start + content_len)
+                    .saturating_sub(line_start)
+                    .max(start);
+                ranges.push(start..end);
+                starting_here += u64::from(found.start >= line_start);
+            }
+            let selected = if lines.reached_max_count() {
+                None
+            } else if self.invert_match {
+                ranges.is_empty().then(Vec::new)
+            } else {
+                Some(ranges).filter(|ranges| !ranges.is_empty())
+            };
+            let found = if self.invert_match { 0 } else { starting_here };
+            lines.select_line(line, selected, found)?;
+            line_start = line_end;
+        }
+        lines.finish()
+    }
+}
+
+/// Passes each line of `haystack` to `lines` in turn, until it has nothing more to print.
+fn search_lines(haystack: &[u8], mut lines: LineSelector) -> Result<Stats> {
+    for line in haystack.split_inclusive(|&byte| byte == b'\n') {
+        if lines.is_done() {
+            break;
+        }
+        lines.next_line(line)?;
+    }
+    lines.finish()
+}
+
+/// The line-at-a-time part of a search: decides whether each line is selected, and passes it to
+/// the output along with any context.
+struct LineSelector<'a> {
+    searcher: &'a Searcher,
+    matcher: &'a dyn Matcher,
+    path: Option<&'a Path>,
+    output: &'a mut dyn Sink,
+    context: ContextWindow,
+    /// Holds the rewritten line when replacing, reused from line to line.
+    replaced: Vec<u8>,
+    /// The number of the last line seen.
+    number: u64,
+    /// The offset of the start of the next line.
+    offset: u64,
+    stats: Stats,
+}
+
+impl<'a> LineSelector<'a> {
+    fn new(
+        searcher: &'a Searcher,
+        matcher: &'a dyn Matcher,
+        path: Option<&'a Path>,
+        output: &'a mut dyn Sink,
+    ) -> Result<Self> {
+        output.begin(path)?;
+        Ok(Self {
+            searcher,
+            matcher,
+            path,
+            output,
+            context: ContextWindow::new(searcher.before_context, searcher.after_context),
+            replaced: Vec::new(),
+            number: 0,
+            offset: 0,
+            stats: Stats {
+                searches: 1,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Steps over lines that are known not to be selected, without looking at them.
+    fn skip(&mut self, lines: &[u8]) {
+        self.number += memchr::memchr_iter(b'\n', lines).count() as u64;
+        self.offset += lines.len() as u64;
+    }
+
+    fn reached_max_count(&self) -> bool {
+        self.searcher
+            .max_count
+            .is_some_and(|max| self.stats.matched_lines >= max)
+    }
+
+    /// Whether there's nothing more to print for this input, so the rest of it can be skipped.
+    fn is_done(&self) -> bool {
+        self.reached_max_count() && !self.context.in_trailing_context()
+    }
+
+    fn next_line(&mut self, line: &[u8]) -> Result<()> {
+        let content = trim_line_terminator(line);
+        let selected = if self.reached_max_count() {
+            // Past the limit, lines can only be trailing context
+            None
+        } else if self.searcher.invert_match {
+            // Inverted lines contain nothing to highlight, so a yes/no answer is enough
+            (!self.matcher.is_match(content)).then(Vec::new)
+        } else {
+            Some(self.matcher.find_all(content)).filter(|matches| !matches.is_empty())
+        };
+        let found = selected.as_ref().map_or(0, Vec::len) as u64;
+        self.select_line(line, selected, found)
+    }
+
+    /// Passes `line` on to the output, as a selected line if `selected` holds the matches on it
+    /// and as possible context otherwise. `found` is how many matches to count towards the stats.
+    fn select_line(
+        &mut self,
+        line: &[u8],
+        selected: Option<Vec<Range<usize>>>,
+        found: u64,
+    ) -> Result<()> {
+        self.number += 1;
+        let current = Line {
+            path: self.path,
+            number: self.number,
+            offset: self.offset,
+            content: trim_line_terminator(line),
+        };
+        self.offset += line.len() as u64;
+
+        match selected {
+            Some(matches) => {
+                self.context.start_match(&current, self.output)?;
+                match &self.searcher.replace {
+                    Some(template) => {
+                        self.replaced.clear();
+                        let replacements = replace::replace_matches(
+                            self.matcher,
+                            current.content,
+                            &matches,
+                            template,
+                            &mut self.replaced,
+                        );
+                        let rewritten = Line {
+                            content: &self.replaced,
+                            ..current
+                        };
+                        self.output.write_line(&rewritten, &replacements)?;
+                    }
+                    None => self.output.write_line(&current, &matches)?,
+                }
+                self.stats.matched_lines += 1;
+                self.stats.matches += found;
+            }
+            None => self.context.other_line(&current, self.output)?,
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Stats> {
+        self.stats.bytes_searched = self.offset;
+        self.stats.searches_with_match = u64::from(self.stats.matched_lines > 0);
+        self.output.end(self.path, &self.stats)?;
+        Ok(self.stats)
+    }
+}
+
+/// Stands in for the sink while a binary input is searched, telling it the input matched instead
+/// of passing on the lines themselves.
+struct BinaryMatches<'a> {
+    output: &'a mut dyn Sink,
+    path: Option<&'a Path>,
+    reported: bool,
+}
+
+impl Sink for BinaryMatches<'_> {
+    fn begin(&mut self, path: Option<&Path>) -> io::Result<()> {
+        self.output.begin(path)
+    }
+
+    fn write_line(&mut self, _line: &Line, _matches: &[Range<usize>]) -> io::Result<()> {
+        if self.reported {
+            return Ok(());
+        }
+        self.reported = true;
+        self.output.binary_match(self.path)
+    }
+
+    fn write_context(&mut self, _line: &Line) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn end(&mut self, path: Option<&Path>, stats: &Stats) -> io::Result<()> {
+        self.output.end(path, stats)
+    }
+
+    fn counts_lines(&self) -> bool {
+        self.output.counts_lines()
+    }
+}
+
+/// `line` without its `\n` or `\r\n` terminator, if it has one.
+pub(crate) fn trim_line_terminator(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{DecodingInput, FileInput, TextEncoding};
+    use crate::matcher::{Boundary, FuzzyMatcher, LiteralMatcher, PcreMatcher, RegexMatcher};
+    use std::io::{self, Cursor};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Records every line it is given so tests can inspect them.
+    #[derive(Default)]
+    struct RecordingOutput {
+        lines: Vec<(Option<String>, String, Vec<Range<usize>>)>,
+        context: Vec<(Option<String>, String)>,
+        /// The line number and offset of every line, matching or context, in order.
+        positions: Vec<(u64, u64)>,
+        separators: usize,
+        binary_matches: Vec<Option<String>>,
+        ends: Vec<Stats>,
+        counts_lines: bool,
+    }
+
+    impl Sink for RecordingOutput {
+        fn write_line(&mut self, line: &Line, matches: &[Range<usize>]) -> io::Result<()> {
+            self.lines.push((
+                line.path.map(|path| path.display().to_string()),
+                String::from_utf8_lossy(line.content).into_owned(),
+                matches.to_vec(),
+            ));
+            self.positions.push((line.number, line.offset));
+            Ok(())
+        }
+
+        fn write_context(&mut self, line: &Line) -> io::Result<()> {
+            self.context.push((
+                line.path.map(|path| path.display().to_string()),
+                String::from_utf8_lossy(line.content).into_owned(),
+            ));
+            self.positions.push((line.number, line.offset));
+            Ok(())
+        }
+
+        fn binary_match(&mut self, path: Option<&Path>) -> io::Result<()> {
+            self.binary_matches
+                .push(path.map(|path| path.display().to_string()));
+            Ok(())
+        }
+
+        fn write_separator(&mut self) -> io::Result<()> {
+            self.separators += 1;
+            Ok(())
+        }
+
+        fn end(&mut self, _path: Option<&Path>, stats: &Stats) -> io::Result<()> {
+            self.ends.push(*stats);
+            Ok(())
+        }
+
+        fn counts_lines(&self) -> bool {
+            self.counts_lines
+        }
+    }
+
+    const POEM: &str = "Roses are red\nViolets are blue\nSugar is sweet\r\nAnd so are you";
+
+    fn run(matcher: &dyn Matcher, invert_match: bool) -> RecordingOutput {
+        let mut output = RecordingOutput::default();
+        let searcher = Searcher {
+            invert_match,
+            ..Default::default()
+        };
+        searcher
+            .search_reader(matcher, &mut Cursor::new(POEM), None, &mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_search_literal() {
+        let output = run(&LiteralMatcher::new("are", false).unwrap(), false);
+        let lines: Vec<_> = output.lines.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(
+            lines,
+            vec!["Roses are red", "Violets are blue", "And so are you"]
+        );
+        assert_eq!(output.lines[0].2, vec![6..9]);
+    }
+
+    #[test]
+    fn test_search_strips_line_terminators() {
+        let output = run(&RegexMatcher::new("sweet$", false).unwrap(), false);
+        assert_eq!(output.lines.len(), 1);
+        assert_eq!(output.lines[0].1, "Sugar is sweet");
+    }
+
+    #[test]
+    fn test_search_invert_match() {
+        let output = run(&LiteralMatcher::new("are", false).unwrap(), true);
+        assert_eq!(output.lines.len(), 1);
+        assert_eq!(output.lines[0].1, "Sugar is sweet");
+        assert!(output.lines[0].2.is_empty());
+    }
+
+    #[test]
+    fn test_search_ignore_case() {
+        let output = run(&LiteralMatcher::new("ROSES", true).unwrap(), false);
+        assert_eq!(output.lines.len(), 1);
+        assert_eq!(output.lines[0].2, vec![0..5]);
+    }
+
+    #[test]
+    fn test_search_passes_path_through() {
+        let mut output = RecordingOutput::default();
+        let searcher = Searcher::default();
+        let stats = searcher
+            .search_reader(
+                &LiteralMatcher::new("blue", false).unwrap(),
+                &mut Cursor::new(POEM),
+                Some(Path::new("poem.txt")),
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(stats.matched_lines, 1);
+        assert_eq!(output.lines[0].0.as_deref(), Some("poem.txt"));
+    }
+
+    #[test]
+    fn test_search_source() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        io::Write::write_all(&mut file, POEM.as_bytes()).unwrap();
+        let matcher = LiteralMatcher::new("are", false).unwrap();
+        let mapped = FileInput::new(file.path());
+        // Decoding from another encoding has to stream
+        let streamed = DecodingInput::new(
+            FileInput::new(file.path()),
+            TextEncoding::Fixed(encoding_rs::WINDOWS_1252),
+        );
+        assert!(mapped.map().unwrap().is_some());
+        assert!(streamed.map().unwrap().is_none());
+
+        for source in [&mapped as &dyn Source, &streamed] {
+            let mut output = RecordingOutput::default();
+            let stats = Searcher::default()
+                .search_source(&matcher, source, &mut output)
+                .unwrap();
+            assert_eq!(stats.matched_lines, 3);
+            assert_eq!(
+                output.lines[2].0.as_deref(),
+                Some(file.path().display().to_string().as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn test_search_with_context() {
+        let mut output = RecordingOutput::default();
+        let searcher = Searcher {
+            before_context: 1,
+            after_context: 1,
+            ..Default::default()
+        };
+        searcher
+            .search_reader(
+                &LiteralMatcher::new("Violets", false).unwrap(),
+                &mut Cursor::new(POEM),
+                Some(Path::new("poem.txt")),
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(output.lines.len(), 1);
+        let context: Vec<_> = output.context.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(context, vec!["Roses are red", "Sugar is sweet"]);
+        assert_eq!(output.context[0].0.as_deref(), Some("poem.txt"));
+        assert_eq!(output.separators, 1);
+    }
+
+    #[test]
+    fn test_search_context_with_invert_match() {
+        let mut output = RecordingOutput::default();
+        let searcher = Searcher {
+            invert_match: true,
+            before_context: 1,
+            ..Default::default()
+        };
+        searcher
+            .search_reader(
+                &LiteralMatcher::new("are", false).unwrap(),
+                &mut Cursor::new(POEM),
+                None,
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(output.lines[0].1, "Sugar is sweet");
+        assert_eq!(output.context.len(), 1);
+        assert_eq!(output.context[0].1, "Violets are blue");
+    }
+
+    /// Runs both search paths over `haystack` and checks they print the same thing.
+    fn assert_paths_agree(searcher: &Searcher, matcher: &dyn Matcher, haystack: &str) {
+        let mut streamed = RecordingOutput::default();
+        let streamed_stats = searcher
+            .search_reader(matcher, &mut Cursor::new(haystack), None, &mut streamed)
+            .unwrap();
+        let mut sliced = RecordingOutput::default();
+        let sliced_stats = searcher
+            .search_slice(matcher, haystack.as_bytes(), None, &mut sliced)
+            .unwrap();
+        assert_eq!(streamed_stats, sliced_stats, "haystack: {haystack:?}");
+        assert_eq!(streamed.lines, sliced.lines, "haystack: {haystack:?}");
+        assert_eq!(streamed.context, sliced.context, "haystack: {haystack:?}");
+        assert_eq!(
+            streamed.positions, sliced.positions,
+            "haystack: {haystack:?}"
+        );
+        assert_eq!(streamed.ends, sliced.ends, "haystack: {haystack:?}");
+    }
+
+    #[test]
+    fn test_search_slice_matches_search_reader() {
+        let haystacks = [
+            POEM,
+            "",
+            "\n\n",
+            "no trailing newline",
+            "are\nare\n",
+            "Roses\r\nare\r\nred\r\n",
+        ];
+        let patterns = [
+            "are", "^are", "are$", r"e\s+a", r"d\sV", "x*", "^$", "[^a]+", "sweet$",
+        ];
+        // -w and -x work by wrapping the pattern, and have to hold up with -v too
+        let bounded = [Boundary::Word.wrap("are"), Boundary::Line.wrap("are|red")];
+        let patterns = patterns
+            .iter()
+            .copied()
+            .chain(bounded.iter().map(String::as_str));
+        for haystack in haystacks {
+            for pattern in patterns.clone() {
+                let matcher = RegexMatcher::new(pattern, false).unwrap();
+                assert_paths_agree(&Searcher::default(), &matcher, haystack);
+                let inverted = Searcher {
+                    invert_match: true,
+                    ..Default::default()
+                };
+                assert_paths_agree(&inverted, &matcher, haystack);
+                let with_context = Searcher {
+                    before_context: 1,
+                    after_context: 1,
+                    ..Default::default()
+                };
+                assert_paths_agree(&with_context, &matcher, haystack);
+            }
+            let literal = LiteralMatcher::new("ARE", true).unwrap();
+            assert_paths_agree(&Searcher::default(), &literal, haystack);
+            let fuzzy = FuzzyMatcher::new("aRe", 1, false);
+            assert_paths_agree(&Searcher::default(), &fuzzy, haystack);
+        }
+    }
+
+    #[test]
+    fn test_search_slice_match_across_lines() {
+        // The buffer-wide scan finds "d\nV" first, but neither line matches on its own
+        let mut output = RecordingOutput::default();
+        Searcher::default()
+            .search_slice(
+                &RegexMatcher::new(r"d\sV|blue", false).unwrap(),
+                POEM.as_bytes(),
+                None,
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(output.lines.len(), 1);
+        assert_eq!(output.lines[0].1, "Violets are blue");
+    }
+
+    #[test]
+    fn test_search_slice_long_matches_across_lines() {
+        // Each scan for "[^#]+#" runs all the way to the last line, which took quadratic time
+        // when it was repeated from every line
+        let haystack = format!("{}last # line\n", "some line\n".repeat(20_000));
+        let mut output = RecordingOutput::default();
+        let stats = Searcher::default()
+            .search_slice(
+                &RegexMatcher::new("[^#]+#", false).unwrap(),
+                haystack.as_bytes(),
+                None,
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(output.lines.len(), 1);
+        assert_eq!(output.lines[0].1, "last # line");
+        assert_eq!(output.positions, vec![(20_001, 200_000)]);
+        assert_eq!(stats.bytes_searched, haystack.len() as u64);
+    }
+
+    /// Passes everything on to a matcher, adding up the length of every haystack it's given.
+    struct MeasuringMatcher<M> {
+        inner: M,
+        searched: AtomicUsize,
+    }
+
+    impl<M: Matcher> Matcher for MeasuringMatcher<M> {
+        fn find_at(&self, haystack: &[u8], at: usize) -> Option<Range<usize>> {
+            self.searched
+                .fetch_add(haystack.len() - at, Ordering::Relaxed);
+            self.inner.find_at(haystack, at)
+        }
+
+        fn find_all(&self, haystack: &[u8]) -> Vec<Range<usize>> {
+            self.searched.fetch_add(haystack.len(), Ordering::Relaxed);
+            self.inner.find_all(haystack)
+        }
+
+        fn searches_lines(&self) -> bool {
+            self.inner.searches_lines()
+        }
+    }
+
+    #[test]
+    fn test_search_slice_line_by_line_for_backtracking_matchers() {
+        // Asked for one match at a time in the whole buffer, a backtracking matcher went over
+        // the rest of it for every match, so the work done grew with the square of its length
+        for lines in [1_000, 10_000] {
+            let haystack = "it is is here\n".repeat(lines);
+            let matcher = MeasuringMatcher {
+                inner: PcreMatcher::new(r"\b(\w+) \1\b", false).unwrap(),
+                searched: AtomicUsize::new(0),
+            };
+            let stats = Searcher::default()
+                .search_slice(
+                    &matcher,
+                    haystack.as_bytes(),
+                    None,
+                    &mut RecordingOutput::default(),
+                )
+                .unwrap();
+            assert_eq!(stats.matched_lines, lines as u64);
+            assert!(matcher.searched.into_inner() <= haystack.len());
+        }
+    }
+
+    #[test]
+    fn test_search_with_replace() {
+        let mut output = RecordingOutput::default();
+        let searcher = Searcher {
+            replace: Some(Template::parse("[$1]")),
+            before_context: 1,
+            ..Default::default()
+        };
+        let stats = searcher
+            .search_slice(
+                &RegexMatcher::new(r"(\w+) are", false).unwrap(),
+                POEM.as_bytes(),
+                None,
+                &mut output,
+            )
+            .unwrap();
+        let lines: Vec<_> = output.lines.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(lines, vec!["[Roses] red", "[Violets] blue", "And [so] you"]);
+        assert_eq!(output.lines[1].2, vec![0..9]);
+        // Context lines are left as they are
+        assert_eq!(output.context[0].1, "Sugar is sweet");
+        assert_eq!(stats.matches, 3);
+    }
+
+    #[test]
+    fn test_search_max_count() {
+        let haystack = "a1\nb\na2\na3\nc\na4\n";
+        let matcher = LiteralMatcher::new("a", false).unwrap();
+        let searcher = Searcher {
+            max_count: Some(2),
+            ..Default::default()
+        };
+        let mut output = RecordingOutput::default();
+        let stats = searcher
+            .search_slice(&matcher, haystack.as_bytes(), None, &mut output)
+            .unwrap();
+        let lines: Vec<_> = output.lines.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(lines, vec!["a1", "a2"]);
+        assert_eq!(stats.matched_lines, 2);
+        // The search stopped at the second match
+        assert_eq!(stats.bytes_searched, 8);
+
+        // Trailing context is still printed, even if it would have matched
+        let with_context = Searcher {
+            max_count: Some(1),
+            after_context: 2,
+            ..Default::default()
+        };
+        let mut output = RecordingOutput::default();
+        with_context
+            .search_slice(&matcher, haystack.as_bytes(), None, &mut output)
+            .unwrap();
+        let context: Vec<_> = output.context.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(context, vec!["b", "a2"]);
+        assert_eq!(output.lines.len(), 1);
+
+        for searcher in [searcher, with_context] {
+            assert_paths_agree(&searcher, &matcher, haystack);
+        }
+        let none = Searcher {
+            max_count: Some(0),
+            ..Default::default()
+        };
+        assert_paths_agree(&none, &matcher, haystack);
+    }
+
+    fn run_multiline(searcher: Searcher, pattern: &str, haystack: &str) -> RecordingOutput {
+        let searcher = Searcher {
+            multiline: true,
+            ..searcher
+        };
+        let matcher = RegexMatcher::new(pattern, false).unwrap();
+        let mut output = RecordingOutput::default();
+        searcher
+            .search_slice(&matcher, haystack.as_bytes(), None, &mut output)
+            .unwrap();
+        assert_paths_agree(&searcher, &matcher, haystack);
+        output
+    }
+
+    #[test]
+    fn test_search_multiline() {
+        let output = run_multiline(Searcher::default(), r"red\nViolets", POEM);
+        let lines: Vec<_> = output.lines.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(lines, vec!["Roses are red", "Violets are blue"]);
+        assert_eq!(output.lines[0].2, vec![10..13]);
+        assert_eq!(output.lines[1].2, vec![0..7]);
+        assert_eq!(output.positions, vec![(1, 0), (2, 14)]);
+        assert_eq!(output.ends[0].matches, 1);
+        assert_eq!(output.ends[0].matched_lines, 2);
+    }
+
+    #[test]
+    fn test_search_multiline_blocks_and_context() {
+        let haystack = "a\nstart\nmiddle\nend\nb\nc\nstart\nend\n";
+        let searcher = Searcher {
+            after_context: 1,
+            ..Default::default()
+        };
+        let output = run_multiline(searcher, r"start\n(?:.*\n)*?end", haystack);
+        let lines: Vec<_> = output.lines.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(lines, vec!["start", "middle", "end", "start", "end"]);
+        // The whole of "middle" is part of the match
+        assert_eq!(output.lines[1].2, vec![0..6]);
+        let context: Vec<_> = output.context.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(context, vec!["b"]);
+        assert_eq!(output.separators, 2);
+    }
+
+    #[test]
+    fn test_search_multiline_single_line_matches() {
+        // Without a line break in the pattern, it's the same as searching line by line
+        let output = run_multiline(Searcher::default(), "are$|^$", "are\n\nyou are");
+        let lines: Vec<_> = output.lines.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(lines, vec!["are", "", "you are"]);
+
+        let inverted = Searcher {
+            invert_match: true,
+            ..Default::default()
+        };
+        let output = run_multiline(inverted, r"d\nV", POEM);
+        let lines: Vec<_> = output.lines.iter().map(|line| line.1.as_str()).collect();
+        assert_eq!(lines, vec!["Sugar is sweet", "And so are you"]);
+    }
+
+    #[test]
+    fn test_search_reports_positions_and_stats() {
+        let mut output = RecordingOutput::default();
+        let stats = Searcher::default()
+            .search_slice(
+                &LiteralMatcher::new("e", false).unwrap(),
+                b"abc\nbee\nxyz\r\nsee\n",
+                None,
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(output.positions, vec![(2, 4), (4, 13)]);
+        assert_eq!(
+            stats,
+            Stats {
+                searches: 1,
+                searches_with_match: 1,
+                bytes_searched: 17,
+                matched_lines: 2,
+                matches: 4,
+            }
+        );
+        assert_eq!(output.ends, vec![stats]);
+    }
+
+    const BINARY: &[u8] = b"ELF\0\x01 needle\nno\nneedle again\n";
+
+    fn run_binary(
+        binary_files: BinaryFiles,
+        slice: bool,
+        counts_lines: bool,
+    ) -> (RecordingOutput, Stats) {
+        let matcher = LiteralMatcher::new("needle", false).unwrap();
+        let searcher = Searcher {
+            binary_files,
+            before_context: 1,
+            ..Default::default()
+        };
+        let mut output = RecordingOutput {
+            counts_lines,
+            ..Default::default()
+        };
+        let path = Some(Path::new("a.out"));
+        let stats = if slice {
+            searcher.search_slice(&matcher, BINARY, path, &mut output)
+        } else {
+            searcher.search_reader(&matcher, &mut Cursor::new(BINARY), path, &mut output)
+        }
+        .unwrap();
+        (output, stats)
+    }
+
+    #[test]
+    fn test_search_binary_files() {
+        for slice in [false, true] {
+            // The match is only reported once, no lines get through, and the search stops there
+            let (output, stats) = run_binary(BinaryFiles::Binary, slice, false);
+            assert_eq!(output.binary_matches, vec![Some("a.out".to_string())]);
+            assert!(output.lines.is_empty() && output.context.is_empty());
+            assert_eq!(output.separators, 0);
+            assert_eq!(stats.matched_lines, 1);
+            assert_eq!(output.ends, vec![stats]);
+
+            // Unless the sink needs every selected line counted
+            let (output, stats) = run_binary(BinaryFiles::Binary, slice, true);
+            assert_eq!(output.binary_matches, vec![Some("a.out".to_string())]);
+            assert!(output.lines.is_empty() && output.context.is_empty());
+            assert_eq!(stats.matched_lines, 2);
+            assert_eq!(output.ends, vec![stats]);
+
+            let (output, stats) = run_binary(BinaryFiles::Text, slice, false);
+            assert!(output.binary_matches.is_empty());
+            assert_eq!(output.lines.len(), 2);
+            assert_eq!(output.lines[0].1, "ELF\0\x01 needle");
+            assert_eq!(stats.matched_lines, 2);
+
+            let (output, stats) = run_binary(BinaryFiles::WithoutMatch, slice, false);
+            assert!(output.binary_matches.is_empty() && output.lines.is_empty());
+            assert_eq!(stats.searches, 1);
+            assert_eq!(stats.matched_lines, 0);
+            assert_eq!(output.ends, vec![stats]);
+        }
+    }
+
+    #[test]
+    fn test_search_skips_header() {
+        let matcher = LiteralMatcher::new("name", false).unwrap();
+        let haystack = b"name,city\nname1,x\ny,z\nname2,w\n";
+        for (multiline, after_context) in [(false, 0), (false, 1), (true, 0)] {
+            let searcher = Searcher {
+                skip_header: true,
+                multiline,
+                after_context,
+                ..Default::default()
+            };
+            let mut from_slice = RecordingOutput::default();
+            let stats = searcher
+                .search_slice(&matcher, haystack, None, &mut from_slice)
+                .unwrap();
+            let mut from_reader = RecordingOutput::default();
+            searcher
+                .search_reader(&matcher, &mut Cursor::new(haystack), None, &mut from_reader)
+                .unwrap();
+            for output in [&from_slice, &from_reader] {
+                let lines: Vec<_> = output.lines.iter().map(|line| line.1.as_str()).collect();
+                assert_eq!(lines, ["name1,x", "name2,w"]);
+                assert_eq!(output.positions[0], (2, 10));
+            }
+            assert_eq!(stats.matched_lines, 2);
+            assert_eq!(stats.bytes_searched, haystack.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_search_binary_without_a_match_is_not_reported() {
+        let mut output = RecordingOutput::default();
+        Searcher::default()
+            .search_slice(
+                &LiteralMatcher::new("absent", false).unwrap(),
+                BINARY,
+                None,
+                &mut output,
+            )
+            .unwrap();
+        assert!(output.binary_matches.is_empty());
+        assert_eq!(output.ends.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_binary_files() {
+        assert_eq!("binary".parse(), Ok(BinaryFiles::Binary));
+        assert_eq!("text".parse(), Ok(BinaryFiles::Text));
+        assert_eq!("without-match".parse(), Ok(BinaryFiles::WithoutMatch));
+        assert!("hex".parse::<BinaryFiles>().is_err());
+    }
+}
@@ -0,0 +1,437 @@
+mod app;
+
+use crate::Args;
+use anyhow::Result;
+use app::{Action, App, Hit, Message};
+use greprs::input::{FileInput, Source};
+use greprs::matcher::Matcher;
+use greprs::output::{Line as OutputLine, Sink};
+use greprs::search::Searcher;
+use greprs::walk::{self, WalkOptions};
+use ratatui::crossterm::event::{self, Event};
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, List, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, BufRead};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Stop a search once it has found this many lines, since nobody is going to scroll through more.
+const MAX_HITS: usize = 100_000;
+
+/// Browses the results of searching for `needle` in a terminal UI, searching again as the needle
+/// is edited.
+pub fn run(args: &Args, searcher: &Searcher, needle: String) -> Result<()> {
+    let options = args.walk_options()?;
+    // The generation of the search that's wanted, so older ones can notice and stop
+    let current = AtomicU64::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut app = App::new(needle);
+    let mut preview = Preview::default();
+    let mut terminal = ratatui::init();
+
+    let result = thread::scope(|scope| -> Result<()> {
+        let search = |app: &mut App| {
+            let generation = app.start_search();
+            current.store(generation, Ordering::Relaxed);
+            if app.needle.is_empty() {
+                app.searching = false;
+                return;
+            }
+            let matcher = match args.build_matcher(std::slice::from_ref(&app.needle)) {
+                Ok(matcher) => matcher,
+                Err(error) => return app.fail(format!("{error:#}")),
+            };
+            let (options, sender, current) = (options.clone(), sender.clone(), &current);
+            scope.spawn(move || {
+                let search = Search {
+                    generation,
+                    current,
+                    sender,
+                    remaining: MAX_HITS,
+                };
+                search.run(args, searcher, &*matcher, options);
+            });
+        };
+        search(&mut app);
+
+        loop {
+            for (generation, message) in receiver.try_iter() {
+                app.receive(generation, message);
+            }
+            terminal.draw(|frame| draw(frame, &mut app, &mut preview, args))?;
+            if !event::poll(Duration::from_millis(50))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            match app.handle_key(key) {
+                Action::None => {}
+                Action::Search => search(&mut app),
+                Action::Open(path, line_number) => {
+                    terminal = open_in_editor(&path, line_number)?;
+                }
+                Action::Quit => break,
+            }
+        }
+        // Stop whatever is still searching, so the scope can end
+        current.store(0, Ordering::Relaxed);
+        Ok(())
+    });
+    ratatui::restore();
+    result
+}
+
+/// One search running in the background, sending what it finds to the UI.
+struct Search<'a> {
+    generation: u64,
+    current: &'a AtomicU64,
+    sender: Sender<(u64, Message)>,
+    /// How many more lines to find before giving up.
+    remaining: usize,
+}
+
+impl Search<'_> {
+    fn is_current(&self) -> bool {
+        self.current.load(Ordering::Relaxed) == self.generation
+    }
+
+    fn send(&self, message: Message) -> io::Result<()> {
+        self.sender
+            .send((self.generation, message))
+            .map_err(|_| io::Error::other("the TUI has closed"))
+    }
+
+    fn run(
+        mut self,
+        args: &Args,
+        searcher: &Searcher,
+        matcher: &dyn Matcher,
+        options: WalkOptions,
+    ) {
+        for file in walk::walk(&args.files, options) {
+            let result =
+                file.and_then(|file| crate::search_file(args, searcher, matcher, &file, &mut self));
+            if !self.is_current() {
+                return;
+            }
+            if self.remaining == 0 {
+                break;
+            }
+            if let Err(error) = result {
+                let _ = self.send(Message::Error(format!("{error:#}")));
+            }
+        }
+        let _ = self.send(Message::Done(self.remaining == 0));
+    }
+}
+
+impl Sink for Search<'_> {
+    fn write_line(&mut self, line: &OutputLine, matches: &[Range<usize>]) -> io::Result<()> {
+        // Failing stops the file being searched, and the search notices why once it's stopped
+        if !self.is_current() {
+            return Err(io::Error::other("the search was replaced"));
+        }
+        if self.remaining == 0 {
+            return Err(io::Error::other("found too many lines"));
+        }
+        self.remaining -= 1;
+        let path = line.path.map(Path::to_path_buf).unwrap_or_default();
+        self.send(Message::Hit(Hit::new(
+            path,
+            line.number,
+            line.content,
+            matches,
+        )))
+    }
+
+    fn write_context(&mut self, _line: &OutputLine) -> io::Result<()> {
+        // The preview pane shows context for whichever line is selected instead
+        Ok(())
+    }
+
+    fn binary_match(&mut self, path: Option<&Path>) -> io::Result<()> {
+        if !self.is_current() {
+            return Err(io::Error::other("the search was replaced"));
+        }
+        let path = path.map(Path::to_path_buf).unwrap_or_default();
+        self.send(Message::BinaryMatch(path))
+    }
+
+    fn write_separator(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The lines shown around the selected hit, kept until another hit is selected.
+#[derive(Debug, Default)]
+struct Preview {
+    /// The hit and pane height the lines were read for.
+    key: Option<(PathBuf, u64, usize)>,
+    lines: Vec<String>,
+    /// The number of the first line in `lines`.
+    first: u64,
+}
+
+impl Preview {
+    fn update(&mut self, args: &Args, hit: &Hit, height: usize) {
+        let key = (hit.path.clone(), hit.line_number, height);
+        if self.key.as_ref() == Some(&key) {
+            return;
+        }
+        let window = app::preview_window(hit.line_number, height);
+        self.first = window.start;
+        // Read the file the same way it was searched, so decoding and decompression match
+        let input = args.wrap_input(Box::new(FileInput::new(&hit.path)));
+        self.lines = match input.open() {
+            Ok(reader) => read_lines(reader, window),
+            Err(error) => vec![format!("{error:#}")],
+        };
+        self.key = Some(key);
+    }
+}
+
+/// Reads the lines numbered `window` from `reader`, cleaned up for drawing.
+fn read_lines(reader: Box<dyn BufRead>, window: Range<u64>) -> Vec<String> {
+    reader
+        .split(b'\n')
+        .skip((window.start - 1) as usize)
+        .take((window.end - window.start) as usize)
+        .map_while(Result::ok)
+        .map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(&line);
+            app::clean(line, &[]).0
+        })
+        .collect()
+}
+
+fn draw(frame: &mut Frame, app: &mut App, preview: &mut Preview, args: &Args) {
+    let [prompt, body, status] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [results, context] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(body);
+
+    let label = "needle: ";
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![label.bold(), app.needle.as_str().into()])),
+        prompt,
+    );
+    let cursor = (label.len() + app.needle.chars().count()) as u16;
+    frame.set_cursor_position(Position::new(
+        prompt.x + cursor.min(prompt.width.saturating_sub(1)),
+        prompt.y,
+    ));
+
+    draw_results(frame, app, results);
+    draw_preview(frame, app, preview, args, context);
+
+    let status_line = if let Some(error) = &app.error {
+        Line::from(error.as_str().red())
+    } else {
+        let mut spans = vec![Span::raw(format!(
+            "{} lines in {} files",
+            app.hits.len(),
+            app.files
+        ))];
+        if app.searching {
+            spans.push(Span::raw(", searching..."));
+        } else if app.truncated {
+            spans.push(Span::raw(format!(", stopped after {MAX_HITS}")));
+        }
+        if app.binary_matches > 0 {
+            spans.push(Span::raw(format!(
+                " | {} binary files matched",
+                app.binary_matches
+            )));
+        }
+        if let Some((error, count)) = &app.file_error {
+            spans.push(format!(" | {count} unreadable: {error}").yellow());
+        }
+        spans.push(" | arrows move, enter opens in $EDITOR, esc quits".dark_gray());
+        Line::from(spans)
+    };
+    frame.render_widget(Paragraph::new(status_line), status);
+}
+
+fn draw_results(frame: &mut Frame, app: &mut App, area: Rect) {
+    let block = Block::bordered().title("Results");
+    let height = block.inner(area).height as usize;
+    app.scroll_into_view(height);
+    // Only build the lines that are on screen, since there can be a great many hits
+    let visible = app.hits.iter().skip(app.offset).take(height);
+    let items = visible.map(|hit| {
+        let mut spans = vec![
+            hit.path.display().to_string().magenta(),
+            ":".cyan(),
+            hit.line_number.to_string().green(),
+            ":".cyan(),
+        ];
+        spans.extend(highlight(&hit.content, &hit.matches));
+        Line::from(spans)
+    });
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default()
+        .with_selected(Some(app.selected - app.offset).filter(|_| !app.hits.is_empty()));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn draw_preview(frame: &mut Frame, app: &App, preview: &mut Preview, args: &Args, area: Rect) {
+    let Some(hit) = app.selected_hit() else {
+        frame.render_widget(Block::bordered().title("Preview"), area);
+        return;
+    };
+    let block = Block::bordered().title(format!("{}:{}", hit.path.display(), hit.line_number));
+    preview.update(args, hit, block.inner(area).height as usize);
+
+    let lines = preview.lines.iter().enumerate().map(|(index, content)| {
+        let number = preview.first + index as u64;
+        let mut spans = vec![format!("{number:>5} ").dark_gray()];
+        if number == hit.line_number {
+            spans.extend(highlight(&hit.content, &hit.matches));
+            Line::from(spans).bg(Color::DarkGray)
+        } else {
+            spans.push(Span::raw(content.as_str()));
+            Line::from(spans)
+        }
+    });
+    frame.render_widget(Paragraph::new(Text::from_iter(lines)).block(block), area);
+}
+
+/// Splits `content` into spans with the `matches` in it highlighted.
+fn highlight<'a>(content: &'a str, matches: &[Range<usize>]) -> Vec<Span<'a>> {
+    let mut spans = Vec::with_capacity(matches.len() * 2 + 1);
+    let mut printed = 0;
+    for found in matches {
+        spans.push(Span::raw(&content[printed..found.start]));
+        spans.push(content[found.clone()].red().bold());
+        printed = found.end;
+    }
+    spans.push(Span::raw(&content[printed..]));
+    spans
+}
+
+/// Hands the terminal over to the user's editor to open `path` at `line_number`, and takes it
+/// back once the editor exits.
+fn open_in_editor(path: &Path, line_number: u64) -> Result<DefaultTerminal> {
+    let editor = env::var_os("VISUAL").or_else(|| env::var_os("EDITOR"));
+    let command = editor_command(editor, path, line_number);
+    ratatui::restore();
+    // The editor reports its own errors, and the TUI should come back either way
+    let _ = Command::new(&command[0]).args(&command[1..]).status();
+    Ok(ratatui::init())
+}
+
+/// The command line to open `path` at `line_number` with `editor`, which may have arguments of
+/// its own, like `code --wait`. The line is given as `+LINE`, which vi, nano, emacs and most
+/// other terminal editors understand. Without an editor, vi is used.
+fn editor_command(editor: Option<OsString>, path: &Path, line_number: u64) -> Vec<OsString> {
+    let editor = editor
+        .map(|editor| editor.to_string_lossy().into_owned())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let mut command: Vec<OsString> = editor.split_whitespace().map(OsString::from).collect();
+    command.push(format!("+{line_number}").into());
+    command.push(path.into());
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_editor_command() {
+        let path = Path::new("src/main.rs");
+        assert_eq!(
+            editor_command(Some("code --wait".into()), path, 12),
+            ["code", "--wait", "+12", "src/main.rs"]
+        );
+        assert_eq!(editor_command(None, path, 1), ["vi", "+1", "src/main.rs"]);
+        assert_eq!(
+            editor_command(Some(" ".into()), path, 1),
+            ["vi", "+1", "src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn test_read_lines() {
+        let reader = Box::new(Cursor::new(b"one\ntwo\r\nthree\tfour\nfive\n".to_vec()));
+        assert_eq!(read_lines(reader, 2..4), ["two", "three four"]);
+        let reader = Box::new(Cursor::new(b"one\n".to_vec()));
+        assert_eq!(read_lines(reader, 1..10), ["one"]);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let spans = highlight("one fish two", &[0..0, 4..8]);
+        let text: Vec<_> = spans.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(text, ["", "", "one ", "fish", " two"]);
+        assert_eq!(spans[3].style, Style::new().red().bold());
+    }
+
+    #[test]
+    fn test_search_streams_hits_until_replaced() {
+        let current = AtomicU64::new(1);
+        let (sender, receiver) = mpsc::channel();
+        let mut search = Search {
+            generation: 1,
+            current: &current,
+            sender,
+            remaining: 2,
+        };
+        let line = OutputLine {
+            path: Some(Path::new("a.rs")),
+            number: 3,
+            offset: 0,
+            content: b"let x",
+        };
+        search.write_line(&line, &[4..5, 5..5]).unwrap();
+        current.store(2, Ordering::Relaxed);
+        assert!(search.write_line(&line, &[]).is_err());
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [(
+                1,
+                Message::Hit(Hit::new("a.rs".into(), 3, b"let x", &[4..5, 5..5]))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_search_stops_after_too_many_hits() {
+        let current = AtomicU64::new(1);
+        let (sender, receiver) = mpsc::channel();
+        let mut search = Search {
+            generation: 1,
+            current: &current,
+            sender,
+            remaining: 1,
+        };
+        let line = OutputLine {
+            path: None,
+            number: 1,
+            offset: 0,
+            content: b"x",
+        };
+        search.write_line(&line, &[]).unwrap();
+        assert!(search.write_line(&line, &[]).is_err());
+        assert_eq!(receiver.try_iter().count(), 1);
+    }
+}
@@ -0,0 +1,341 @@
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// A matching line found by the search running behind the TUI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub path: PathBuf,
+    pub line_number: u64,
+    /// The line, made safe to draw.
+    pub content: String,
+    /// Where the matches are in `content`.
+    pub matches: Vec<Range<usize>>,
+}
+
+impl Hit {
+    pub fn new(path: PathBuf, line_number: u64, line: &[u8], matches: &[Range<usize>]) -> Self {
+        let (content, matches) = clean(line, matches);
+        Self {
+            path,
+            line_number,
+            content,
+            matches,
+        }
+    }
+}
+
+/// Decodes `line` for drawing, turning tabs and other control characters into spaces so they
+/// can't break the layout, and moves `matches` along with the text they cover.
+pub fn clean(line: &[u8], matches: &[Range<usize>]) -> (String, Vec<Range<usize>>) {
+    let mut content = String::with_capacity(line.len());
+    let push = |content: &mut String, bytes: &[u8]| {
+        let decoded = String::from_utf8_lossy(bytes);
+        content.extend(
+            decoded
+                .chars()
+                .map(|c| if c.is_control() { ' ' } else { c }),
+        );
+    };
+    let mut cleaned = Vec::with_capacity(matches.len());
+    let mut copied = 0;
+    for found in matches.iter().filter(|found| !found.is_empty()) {
+        push(&mut content, &line[copied..found.start]);
+        let start = content.len();
+        push(&mut content, &line[found.clone()]);
+        cleaned.push(start..content.len());
+        copied = found.end;
+    }
+    push(&mut content, &line[copied..]);
+    (content, cleaned)
+}
+
+/// What the search thread sends back, tagged with the generation of the search it's from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Hit(Hit),
+    /// A binary file matched. Its lines aren't fit to show, so it isn't a hit.
+    BinaryMatch(PathBuf),
+    /// A file couldn't be searched.
+    Error(String),
+    /// The search is over. `true` if it stopped early because it found too many lines.
+    Done(bool),
+}
+
+/// What the event loop should do after a key press.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    None,
+    Quit,
+    /// The needle changed, so the search needs to start over.
+    Search,
+    /// Open a file in the editor, at a line.
+    Open(PathBuf, u64),
+}
+
+/// Everything the TUI shows, kept apart from the terminal so it can be tested.
+#[derive(Debug, Default)]
+pub struct App {
+    pub needle: String,
+    pub hits: Vec<Hit>,
+    /// The number of different files in `hits`.
+    pub files: usize,
+    /// The index of the selected hit.
+    pub selected: usize,
+    /// The index of the first hit on screen.
+    pub offset: usize,
+    /// How many hits fit on screen, for paging.
+    pub page_size: usize,
+    pub searching: bool,
+    /// Set when the search stopped early because it found too many lines.
+    pub truncated: bool,
+    /// Why the needle can't be searched for, like an invalid regex.
+    pub error: Option<String>,
+    /// The latest file that couldn't be searched, and how many couldn't be.
+    pub file_error: Option<(String, usize)>,
+    /// How many binary files matched, which are left out of `hits`.
+    pub binary_matches: usize,
+    generation: u64,
+}
+
+impl App {
+    pub fn new(needle: String) -> Self {
+        Self {
+            needle,
+            page_size: 1,
+            ..Default::default()
+        }
+    }
+
+    /// The hit under the cursor, if there are any.
+    pub fn selected_hit(&self) -> Option<&Hit> {
+        self.hits.get(self.selected)
+    }
+
+    /// Forgets the results of the last search and returns the generation of the new one, so
+    /// messages from older searches still on their way can be told apart.
+    pub fn start_search(&mut self) -> u64 {
+        self.generation += 1;
+        self.hits.clear();
+        self.files = 0;
+        self.selected = 0;
+        self.offset = 0;
+        self.searching = true;
+        self.truncated = false;
+        self.error = None;
+        self.file_error = None;
+        self.binary_matches = 0;
+        self.generation
+    }
+
+    /// Marks the current search as impossible, with the reason why.
+    pub fn fail(&mut self, error: String) {
+        self.searching = false;
+        self.error = Some(error);
+    }
+
+    pub fn receive(&mut self, generation: u64, message: Message) {
+        if generation != self.generation {
+            return;
+        }
+        match message {
+            Message::Hit(hit) => {
+                if self.hits.last().is_none_or(|last| last.path != hit.path) {
+                    self.files += 1;
+                }
+                self.hits.push(hit);
+            }
+            Message::BinaryMatch(_) => self.binary_matches += 1,
+            Message::Error(error) => {
+                let count = self.file_error.as_ref().map_or(0, |(_, count)| *count);
+                self.file_error = Some((error, count + 1));
+            }
+            Message::Done(truncated) => {
+                self.searching = false;
+                self.truncated = truncated;
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Action {
+        if key.kind != KeyEventKind::Press {
+            return Action::None;
+        }
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let page = self.page_size.max(1) as isize;
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c') if control => return Action::Quit,
+            KeyCode::Char('u') if control => {
+                self.needle.clear();
+                return Action::Search;
+            }
+            KeyCode::Char('p') if control => self.move_selection(-1),
+            KeyCode::Char('n') if control => self.move_selection(1),
+            KeyCode::Char(c) if !control && !key.modifiers.contains(KeyModifiers::ALT) => {
+                self.needle.push(c);
+                return Action::Search;
+            }
+            KeyCode::Backspace => {
+                return match self.needle.pop() {
+                    Some(_) => Action::Search,
+                    None => Action::None,
+                };
+            }
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-page),
+            KeyCode::PageDown => self.move_selection(page),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = self.hits.len().saturating_sub(1),
+            KeyCode::Enter => {
+                if let Some(hit) = self.selected_hit() {
+                    return Action::Open(hit.path.clone(), hit.line_number);
+                }
+            }
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn move_selection(&mut self, by: isize) {
+        let last = self.hits.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(by).min(last);
+    }
+
+    /// Scrolls the list just enough for the selected hit to be on screen, given that `height`
+    /// hits fit.
+    pub fn scroll_into_view(&mut self, height: usize) {
+        self.page_size = height;
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if height > 0 && self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+    }
+}
+
+/// The lines of a file to preview around `line_number`, keeping it in the middle of a pane
+/// `height` lines tall where the start of the file allows.
+pub fn preview_window(line_number: u64, height: usize) -> Range<u64> {
+    let start = line_number.saturating_sub(height as u64 / 2).max(1);
+    start..start + height as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(path: &str, line_number: u64) -> Hit {
+        Hit::new(path.into(), line_number, b"fn main() {}", &[0..2, 3..7])
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Action {
+        app.handle_key(KeyEvent::from(code))
+    }
+
+    fn control(app: &mut App, c: char) -> Action {
+        app.handle_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
+
+    #[test]
+    fn test_clean() {
+        let (content, matches) = clean(b"\tlet \x1b[x] = caf\xc3\xa9", &[5..9, 12..17]);
+        assert_eq!(content, " let  [x] = café");
+        assert_eq!(&content[matches[0].clone()], " [x]");
+        assert_eq!(&content[matches[1].clone()], "café");
+
+        // Invalid UTF-8 grows when decoded, and the matches after it move along
+        let (content, matches) = clean(b"\xff ab", &[2..4, 4..4]);
+        assert_eq!(content, "\u{fffd} ab");
+        assert_eq!(matches, vec![4..6]);
+    }
+
+    #[test]
+    fn test_editing_the_needle_restarts_the_search() {
+        let mut app = App::new("fo".to_string());
+        assert_eq!(press(&mut app, KeyCode::Char('o')), Action::Search);
+        assert_eq!(app.needle, "foo");
+        assert_eq!(press(&mut app, KeyCode::Backspace), Action::Search);
+        assert_eq!(app.needle, "fo");
+        assert_eq!(control(&mut app, 'u'), Action::Search);
+        assert_eq!(app.needle, "");
+        // Nothing left to delete, so nothing changed
+        assert_eq!(press(&mut app, KeyCode::Backspace), Action::None);
+        assert_eq!(press(&mut app, KeyCode::Esc), Action::Quit);
+        assert_eq!(control(&mut app, 'c'), Action::Quit);
+    }
+
+    #[test]
+    fn test_messages_from_old_searches_are_ignored() {
+        let mut app = App::new("x".to_string());
+        let old = app.start_search();
+        app.receive(old, Message::Hit(hit("a.rs", 1)));
+        let new = app.start_search();
+        assert!(app.hits.is_empty());
+        app.receive(old, Message::Hit(hit("a.rs", 2)));
+        app.receive(old, Message::Done(false));
+        assert!(app.hits.is_empty());
+        assert!(app.searching);
+
+        app.receive(new, Message::Hit(hit("a.rs", 3)));
+        app.receive(new, Message::Hit(hit("a.rs", 4)));
+        app.receive(new, Message::Hit(hit("b.rs", 1)));
+        app.receive(new, Message::Error("failed to open c.rs".to_string()));
+        app.receive(new, Message::BinaryMatch(PathBuf::from("d.bin")));
+        app.receive(new, Message::Done(true));
+        assert_eq!(app.hits.len(), 3);
+        assert_eq!(app.files, 2);
+        assert_eq!(app.binary_matches, 1);
+        assert_eq!(app.file_error, Some(("failed to open c.rs".to_string(), 1)));
+        assert!(!app.searching);
+        assert!(app.truncated);
+    }
+
+    #[test]
+    fn test_selection_and_scrolling() {
+        let mut app = App::new("x".to_string());
+        let generation = app.start_search();
+        for line_number in 1..=10 {
+            app.receive(generation, Message::Hit(hit("a.rs", line_number)));
+        }
+        app.scroll_into_view(4);
+
+        press(&mut app, KeyCode::Up);
+        assert_eq!(app.selected, 0);
+        press(&mut app, KeyCode::PageDown);
+        control(&mut app, 'n');
+        assert_eq!(app.selected, 5);
+        app.scroll_into_view(4);
+        assert_eq!(app.offset, 2);
+
+        press(&mut app, KeyCode::End);
+        assert_eq!(app.selected, 9);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.selected, 9);
+        app.scroll_into_view(4);
+        assert_eq!(app.offset, 6);
+
+        press(&mut app, KeyCode::Home);
+        app.scroll_into_view(4);
+        assert_eq!(app.offset, 0);
+        assert_eq!(
+            press(&mut app, KeyCode::Enter),
+            Action::Open("a.rs".into(), 1)
+        );
+    }
+
+    #[test]
+    fn test_enter_without_hits() {
+        let mut app = App::new("x".to_string());
+        assert_eq!(press(&mut app, KeyCode::Enter), Action::None);
+        assert_eq!(app.selected_hit(), None);
+    }
+
+    #[test]
+    fn test_preview_window() {
+        assert_eq!(preview_window(50, 10), 45..55);
+        assert_eq!(preview_window(2, 10), 1..11);
+        assert_eq!(preview_window(1, 0), 1..1);
+    }
+}
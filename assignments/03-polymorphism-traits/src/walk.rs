@@ -0,0 +1,183 @@
+mod filter;
+
+pub use filter::{FilterOptions, PathFilter};
+
+use anyhow::Result;
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+
+/// Controls which files a recursive walk visits.
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+    /// Also visit hidden files and directories.
+    pub hidden: bool,
+    /// Don't respect `.gitignore`, `.ignore` and similar files.
+    pub no_ignore: bool,
+    /// Skips files and directories by their paths. Files named directly are never skipped.
+    pub filter: PathFilter,
+}
+
+/// A file found while walking the paths given on the command line.
+#[derive(Debug, PartialEq)]
+pub struct WalkedFile {
+    pub path: PathBuf,
+    /// Whether the file was named directly rather than found inside a directory.
+    pub explicit: bool,
+}
+
+/// Expands `paths` into the files beneath them, in a stable order. Errors for individual entries
+/// (e.g. permission denied) are returned inline so the caller can report them and keep going.
+pub fn walk(paths: &[PathBuf], options: WalkOptions) -> impl Iterator<Item = Result<WalkedFile>> {
+    let (first, rest) = paths.split_first().expect("walk needs at least one path");
+    let mut builder = WalkBuilder::new(first);
+    for path in rest {
+        builder.add(path);
+    }
+    builder
+        .hidden(!options.hidden)
+        .ignore(!options.no_ignore)
+        .parents(!options.no_ignore)
+        .git_ignore(!options.no_ignore)
+        .git_global(!options.no_ignore)
+        .git_exclude(!options.no_ignore)
+        // Respect .gitignore files even when the tree isn't a git checkout
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    let filter = options.filter;
+    builder.filter_entry(move |entry| {
+        let is_dir = entry.file_type().is_some_and(|kind| kind.is_dir());
+        filter.matches(entry.path(), is_dir)
+    });
+
+    builder.build().filter_map(|entry| match entry {
+        Ok(entry) if entry.file_type().is_some_and(|kind| kind.is_dir()) => None,
+        Ok(entry) => Some(Ok(WalkedFile {
+            explicit: entry.depth() == 0,
+            path: entry.into_path(),
+        })),
+        Err(error) => Some(Err(error.into())),
+    })
+}
+
+/// Whether any of `paths` is a directory, in which case results need a path prefix.
+pub fn has_directory(paths: &[PathBuf]) -> bool {
+    paths.iter().any(|path| Path::new(path).is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join(".ignore"), "secret.txt\n").unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.join("src/nested/lib.rs"), "pub fn lib() {}\n").unwrap();
+        fs::write(root.join("target/out.rs"), "generated\n").unwrap();
+        fs::write(root.join("debug.log"), "log\n").unwrap();
+        fs::write(root.join("secret.txt"), "hunter2\n").unwrap();
+        fs::write(root.join(".hidden.rs"), "hidden\n").unwrap();
+        dir
+    }
+
+    fn relative_paths(dir: &TempDir, options: WalkOptions) -> Vec<String> {
+        walk(&[dir.path().to_path_buf()], options)
+            .map(|file| {
+                let file = file.unwrap();
+                assert!(!file.explicit);
+                let relative = file.path.strip_prefix(dir.path()).unwrap();
+                relative.to_string_lossy().replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_walk_respects_ignore_files() {
+        let dir = tree();
+        assert_eq!(
+            relative_paths(&dir, WalkOptions::default()),
+            vec!["src/main.rs", "src/nested/lib.rs"]
+        );
+    }
+
+    #[test]
+    fn test_walk_hidden() {
+        let dir = tree();
+        let options = WalkOptions {
+            hidden: true,
+            ..Default::default()
+        };
+        let paths = relative_paths(&dir, options);
+        assert!(paths.contains(&".hidden.rs".to_string()));
+        assert!(paths.contains(&".gitignore".to_string()));
+        assert!(!paths.contains(&"debug.log".to_string()));
+    }
+
+    #[test]
+    fn test_walk_no_ignore() {
+        let dir = tree();
+        let options = WalkOptions {
+            no_ignore: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            relative_paths(&dir, options),
+            vec![
+                "debug.log",
+                "secret.txt",
+                "src/main.rs",
+                "src/nested/lib.rs",
+                "target/out.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_explicit_files_are_always_visited() {
+        let dir = tree();
+        let hidden = dir.path().join(".hidden.rs");
+        let files: Vec<_> = walk(std::slice::from_ref(&hidden), WalkOptions::default())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            files,
+            vec![WalkedFile {
+                path: hidden,
+                explicit: true
+            }]
+        );
+    }
+
+    #[test]
+    fn test_walk_filter() {
+        let dir = tree();
+        let filter = FilterOptions {
+            globs: vec!["!nested/".to_string()],
+            types: vec!["rust".to_string()],
+            ..Default::default()
+        };
+        let options = WalkOptions {
+            hidden: true,
+            filter: PathFilter::new(dir.path(), &filter).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            relative_paths(&dir, options),
+            vec![".hidden.rs", "src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn test_has_directory() {
+        let dir = tree();
+        assert!(has_directory(&[dir.path().to_path_buf()]));
+        assert!(!has_directory(&[dir.path().join("src/main.rs")]));
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::types::{Types, TypesBuilder};
+use ignore::Match;
+use std::path::Path;
+
+/// The flags that narrow down which files a walk visits.
+#[derive(Debug, Default, Clone)]
+pub struct FilterOptions {
+    /// Globs paths have to match, or with a leading `!`, mustn't match. Later globs take
+    /// precedence over earlier ones, like lines in a `.gitignore`.
+    pub globs: Vec<String>,
+    /// File types to search, like `rust`. If there are any, files of other types are skipped.
+    pub types: Vec<String>,
+    /// File types to skip.
+    pub types_not: Vec<String>,
+    /// Extra type definitions, as `name:glob`, on top of the built-in table.
+    pub type_definitions: Vec<String>,
+}
+
+/// Decides which paths a walk visits, from `--glob` and `--type` style flags.
+///
+/// Only paths are looked at, never the files themselves, so a filter can be used on paths from
+/// anywhere. Globs are checked first: a path a glob includes is visited whatever its type, and
+/// one a glob excludes never is. Directories are only ever skipped by excluding globs, so
+/// `--glob '*.rs'` still looks inside every directory for Rust files.
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    globs: Override,
+    types: Types,
+}
+
+impl PathFilter {
+    /// Builds a filter whose globs are relative to `root`, like `.gitignore` patterns are
+    /// relative to the directory they're in.
+    pub fn new(root: &Path, options: &FilterOptions) -> Result<Self> {
+        let mut globs = OverrideBuilder::new(root);
+        for glob in &options.globs {
+            globs
+                .add(glob)
+                .with_context(|| format!("invalid glob {glob:?}"))?;
+        }
+
+        let mut types = TypesBuilder::new();
+        types.add_defaults();
+        for definition in &options.type_definitions {
+            types
+                .add_def(definition)
+                .with_context(|| format!("invalid type definition {definition:?}"))?;
+        }
+        for name in &options.types {
+            types.select(name);
+        }
+        for name in &options.types_not {
+            types.negate(name);
+        }
+
+        Ok(Self {
+            globs: globs.build()?,
+            types: types.build()?,
+        })
+    }
+
+    /// Whether a walk should visit `path`, or look inside it if it's a directory.
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        match self.globs.matched(path, is_dir) {
+            Match::Whitelist(_) => true,
+            Match::Ignore(_) => false,
+            Match::None => !self.types.matched(path, is_dir).is_ignore(),
+        }
+    }
+}
+
+impl Default for PathFilter {
+    /// A filter that lets everything through.
+    fn default() -> Self {
+        Self {
+            globs: Override::empty(),
+            types: Types::empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(options: FilterOptions) -> PathFilter {
+        PathFilter::new(Path::new("/repo"), &options).unwrap()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_default_lets_everything_through() {
+        let filter = PathFilter::default();
+        assert!(filter.matches(Path::new("/repo/src/main.rs"), false));
+        assert!(filter.matches(Path::new("/repo/target"), true));
+    }
+
+    #[test]
+    fn test_types() {
+        let filter = filter(FilterOptions {
+            types: strings(&["rust"]),
+            ..Default::default()
+        });
+        assert!(filter.matches(Path::new("/repo/src/main.rs"), false));
+        assert!(!filter.matches(Path::new("/repo/README.md"), false));
+        // Directories have no type, so they're always searched
+        assert!(filter.matches(Path::new("/repo/docs"), true));
+
+        let filter = self::filter(FilterOptions {
+            types_not: strings(&["md"]),
+            ..Default::default()
+        });
+        assert!(filter.matches(Path::new("/repo/src/main.rs"), false));
+        assert!(!filter.matches(Path::new("/repo/README.md"), false));
+    }
+
+    #[test]
+    fn test_type_definitions() {
+        let filter = filter(FilterOptions {
+            types: strings(&["notes"]),
+            type_definitions: strings(&["notes:*.{txt,org}"]),
+            ..Default::default()
+        });
+        assert!(filter.matches(Path::new("/repo/todo.org"), false));
+        assert!(filter.matches(Path::new("todo.txt"), false));
+        assert!(!filter.matches(Path::new("/repo/todo.rs"), false));
+    }
+
+    #[test]
+    fn test_unknown_types_and_bad_globs_are_errors() {
+        let root = Path::new("/repo");
+        let options = FilterOptions {
+            types: strings(&["klingon"]),
+            ..Default::default()
+        };
+        assert!(PathFilter::new(root, &options).is_err());
+        let options = FilterOptions {
+            globs: strings(&["src/[unclosed"]),
+            ..Default::default()
+        };
+        assert!(PathFilter::new(root, &options).is_err());
+        let options = FilterOptions {
+            type_definitions: strings(&["no colon"]),
+            ..Default::default()
+        };
+        assert!(PathFilter::new(root, &options).is_err());
+    }
+
+    #[test]
+    fn test_excluding_globs() {
+        let filter = filter(FilterOptions {
+            globs: strings(&["!target/**", "!*.min.js"]),
+            ..Default::default()
+        });
+        assert!(!filter.matches(Path::new("/repo/target/debug/build.rs"), false));
+        assert!(!filter.matches(Path::new("/repo/web/app.min.js"), false));
+        assert!(filter.matches(Path::new("/repo/web/app.js"), false));
+        // Relative paths are taken to be relative to the root
+        assert!(!filter.matches(Path::new("./target/out.rs"), false));
+        assert!(filter.matches(Path::new("src/target.rs"), false));
+    }
+
+    #[test]
+    fn test_including_globs() {
+        let filter = filter(FilterOptions {
+            globs: strings(&["*.rs", "!build.rs"]),
+            ..Default::default()
+        });
+        assert!(filter.matches(Path::new("/repo/src/lib.rs"), false));
+        assert!(!filter.matches(Path::new("/repo/build.rs"), false));
+        assert!(!filter.matches(Path::new("/repo/README.md"), false));
+        assert!(filter.matches(Path::new("/repo/src"), true));
+    }
+
+    #[test]
+    fn test_globs_take_precedence_over_types() {
+        let filter = filter(FilterOptions {
+            globs: strings(&["*.md", "!src/generated.rs"]),
+            types: strings(&["rust"]),
+            ..Default::default()
+        });
+        assert!(filter.matches(Path::new("/repo/README.md"), false));
+        assert!(!filter.matches(Path::new("/repo/src/generated.rs"), false));
+    }
+}
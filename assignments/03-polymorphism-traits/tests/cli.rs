@@ -0,0 +1,313 @@
+//! Runs the compiled `greprs` against the files in `tests/fixtures` and stdin, checking its exit
+//! status and comparing what it prints with the snapshots in `tests/golden`.
+//!
+//! To update the snapshots after changing the output on purpose, run the tests with
+//! `GREPRS_BLESS=1` set and review the changes to `tests/golden` before committing them.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+/// Runs `greprs` in `dir` with `args`, feeding it `stdin`.
+fn greprs_in(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_greprs"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("GREP_COLORS")
+        .env_remove("GREPRS_COLORS")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run greprs");
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Runs `greprs` in the fixtures directory with `args`, feeding it `stdin`.
+fn greprs_with_stdin(args: &[&str], stdin: &[u8]) -> Output {
+    // Keep whoever's running the tests' config files out of it
+    let args: Vec<&str> = ["--no-config"].iter().chain(args).copied().collect();
+    greprs_in(&tests_dir().join("fixtures"), &args, stdin)
+}
+
+fn greprs(args: &[&str]) -> Output {
+    greprs_with_stdin(args, b"")
+}
+
+/// Checks that `output` exited with `code` and printed what's in the snapshot called `name`.
+fn assert_golden(name: &str, output: &Output, code: i32) {
+    assert_eq!(
+        output.status.code(),
+        Some(code),
+        "unexpected exit status, with stderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let path = tests_dir().join("golden").join(format!("{name}.stdout"));
+    if env::var_os("GREPRS_BLESS").is_some() {
+        fs::write(&path, &output.stdout).unwrap();
+        return;
+    }
+    let expected = fs::read(&path)
+        .unwrap_or_else(|_| panic!("no snapshot at {}, run with GREPRS_BLESS=1", path.display()));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&expected),
+        "output doesn't match {}",
+        path.display()
+    );
+}
+
+#[test]
+fn test_literal() {
+    assert_golden("literal", &greprs(&["are", "poem.txt"]), 0);
+    // Regex metacharacters mean nothing without -r
+    assert_golden("no_match", &greprs(&["o.t", "app.log"]), 1);
+    assert_golden("regex_dot", &greprs(&["-r", "o.t", "app.log"]), 0);
+}
+
+#[test]
+fn test_stdin() {
+    let poem = fs::read(tests_dir().join("fixtures/poem.txt")).unwrap();
+    assert_golden("stdin", &greprs_with_stdin(&["-n", "e"], &poem), 0);
+    assert_golden("no_match", &greprs_with_stdin(&["tulips"], &poem), 1);
+}
+
+#[test]
+fn test_regex() {
+    assert_golden("regex", &greprs(&["-r", r"\d+ms$", "app.log"]), 0);
+    assert_golden(
+        "regex_alternation",
+        &greprs(&["-r", "WARN|ERROR", "app.log"]),
+        0,
+    );
+}
+
+#[test]
+fn test_ignore_case() {
+    assert_golden("ignore_case", &greprs(&["-i", "error", "app.log"]), 0);
+    assert_golden("case_sensitive", &greprs(&["error", "app.log"]), 0);
+}
+
+#[test]
+fn test_invert() {
+    assert_golden("invert", &greprs(&["-v", "INFO", "app.log"]), 0);
+    // Every line has a vowel, so inverting leaves nothing
+    assert_golden("no_match", &greprs(&["-v", "-r", "[aeiou]", "poem.txt"]), 1);
+}
+
+#[test]
+fn test_color() {
+    assert_golden("color", &greprs(&["--color=always", "are", "poem.txt"]), 0);
+    assert_golden(
+        "color_with_filename",
+        &greprs(&["--color=always", "-n", "-i", "ERROR", "app.log", "poem.txt"]),
+        0,
+    );
+    // -c picks the color of matches, and leaves everything else uncolored
+    assert_golden("color_red", &greprs(&["-c", "red", "are", "poem.txt"]), 0);
+    assert_golden(
+        "color_red_with_filename",
+        &greprs(&["--color", "red", "-n", "-i", "ERROR", "app.log", "poem.txt"]),
+        0,
+    );
+    // Piped output isn't colored unless asked for
+    assert_golden("literal", &greprs(&["--color=auto", "are", "poem.txt"]), 0);
+}
+
+#[test]
+fn test_several_files() {
+    assert_golden(
+        "several_files",
+        &greprs(&["-r", "^(Sugar|2024-05-02)", "poem.txt", "app.log"]),
+        0,
+    );
+}
+
+#[test]
+fn test_errors() {
+    let output = greprs(&["are", "missing.txt"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.txt"));
+
+    // Like grep, an unreadable file is an error even if another file matched
+    let output = greprs(&["are", "missing.txt", "poem.txt"]);
+    assert_golden("literal_with_filename", &output, 2);
+
+    let output = greprs(&["-r", "(unclosed", "poem.txt"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    let output = greprs(&["--no-such-flag", "are", "poem.txt"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_write_errors() {
+    // Writing to /dev/full always fails for lack of space, which has to be reported
+    for args in [
+        &["are", "poem.txt"][..],
+        &["--count", "are", "poem.txt"],
+        &["-l", "are", "poem.txt"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_greprs"))
+            .arg("--no-config")
+            .args(args)
+            .current_dir(tests_dir().join("fixtures"))
+            .stdout(fs::File::create("/dev/full").unwrap())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("No space left on device"),
+            "{args:?}"
+        );
+    }
+}
+
+#[test]
+fn test_pcre() {
+    assert_golden("pcre", &greprs(&["-P", r"(\w)\1(?=\w)", "poem.txt"]), 0);
+    assert_golden(
+        "pcre_only_matching",
+        &greprs(&["-P", "-o", r"(?<=are )\w+", "poem.txt"]),
+        0,
+    );
+
+    // The default engine can't do look-around, and says what can
+    let output = greprs(&["-r", r"(\w)\1(?=\w)", "poem.txt"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("-P"));
+
+    // A line that takes too long to search is skipped, which counts as an error
+    let long = format!("{}b\naaaa\n", "a".repeat(40));
+    let output = greprs_with_stdin(&["-P", r"^(a+)+\1$"], long.as_bytes());
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "aaaa\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 line took more than"));
+}
+
+#[test]
+fn test_csv_column() {
+    let people = fs::read(tests_dir().join("fixtures/people.csv")).unwrap();
+    let expected = "2:alice,paris\n4:carol,athens\n";
+    for args in [
+        &["-n", "--csv-column", "city", "a", "people.csv"][..],
+        &["-n", "--csv-column", "city", "--no-mmap", "a", "people.csv"],
+    ] {
+        let output = greprs(args);
+        assert_eq!(output.status.code(), Some(0), "{args:?}");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            expected,
+            "{args:?}"
+        );
+    }
+    let output = greprs_with_stdin(&["-n", "--csv-column", "city", "a"], &people);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    // The header names the columns, so it isn't searched as a record, unlike with a number
+    let output = greprs(&["--csv-column", "name", "a", "people.csv"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "alice,paris\ncarol,athens\n"
+    );
+    let output = greprs_with_stdin(&["--csv-column", "1", "a"], &people);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "name,city\nalice,paris\ncarol,athens\n"
+    );
+
+    // A header longer than what's read from the input at once is still found whole
+    let columns: Vec<String> = (0..2000).map(|column| format!("column{column}")).collect();
+    let csv = format!("{},city\n{}paris\n", columns.join(","), ",".repeat(2000));
+    let output = greprs_with_stdin(
+        &["-c", "never", "--csv-column", "city", "paris"],
+        csv.as_bytes(),
+    );
+    assert_eq!(
+        output.status.code(),
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("wide.csv"), &csv).unwrap();
+    let args = [
+        "--no-config",
+        "--no-mmap",
+        "--csv-column",
+        "city",
+        "paris",
+        "wide.csv",
+    ];
+    assert_eq!(greprs_in(dir.path(), &args, b"").status.code(), Some(0));
+}
+
+#[test]
+fn test_indexed() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["poem.txt", "app.log"] {
+        fs::copy(
+            tests_dir().join("fixtures").join(name),
+            dir.path().join(name),
+        )
+        .unwrap();
+    }
+    let search = |args: &[&str]| {
+        let args: Vec<&str> = ["--no-config", "-n"].iter().chain(args).copied().collect();
+        greprs_in(dir.path(), &args, b"")
+    };
+
+    // Without an index there's nothing to narrow the search down with
+    assert_eq!(search(&["--indexed", "are", "."]).status.code(), Some(2));
+    let output = greprs_in(dir.path(), &["index", "build"], b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "indexed 2 files: 2 read, 0 removed\n"
+    );
+
+    // The index only ever leaves out files that can't match, so results are the same
+    fs::write(
+        dir.path().join("new.txt"),
+        "added after the index was built\n",
+    )
+    .unwrap();
+    let patterns: &[&[&str]] = &[
+        &["are"],
+        &["-i", "ERROR"],
+        &["-r", r"\d+ms"],
+        &["-v", "INFO"],
+        &["-w", "red"],
+        &["built"],
+        &["nowhere"],
+    ];
+    for &pattern in patterns {
+        let brute_force = search(&[pattern, &["."]].concat());
+        let indexed = search(&[&["--indexed"], pattern, &["."]].concat());
+        assert_eq!(
+            indexed.status.code(),
+            brute_force.status.code(),
+            "{pattern:?}"
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&indexed.stdout),
+            String::from_utf8_lossy(&brute_force.stdout),
+            "{pattern:?}"
+        );
+    }
+
+    let output = greprs_in(dir.path(), &["index", "build"], b"");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "indexed 3 files: 1 read, 0 removed\n"
+    );
+}